@@ -0,0 +1,955 @@
+//! Bulk writes with `BatchWriteItem` and bulk reads with `BatchGetItem`.
+//!
+//! Requests are sent in chunks, a few chunks at a time. Items or keys DynamoDB
+//! leaves unprocessed, usually when throttling, are sent again with exponential
+//! backoff and jitter. Batches have no conditions, so puts overwrite whatever is
+//! stored and versioned entities are written without a version check.
+
+use crate::{Entity2, Error, Item, ItemError, PrimaryKey, Resolve, TableConfig};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::config::AsyncSleep;
+use aws_sdk_dynamodb::types::{
+    AttributeValue as Av, DeleteRequest, KeysAndAttributes, PutRequest, WriteRequest,
+};
+use futures::StreamExt;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// How many chunks are in flight at once unless set otherwise.
+const DEFAULT_CONCURRENCY: usize = 4;
+/// How often a chunk is sent before what is left unprocessed is given up on.
+const DEFAULT_MAX_ATTEMPTS: u32 = 8;
+/// The wait before the first retry, doubled for every retry after it.
+const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(50);
+
+/// A random wait of up to `base_delay` doubled for every retry before the
+/// `attempt`th, so that chunks throttled together do not retry together.
+fn backoff_delay(attempt: u32, base_delay: Duration) -> Duration {
+    let ceiling = base_delay.saturating_mul(1u32 << attempt.saturating_sub(1).min(16));
+    ceiling.mul_f64(fastrand::f64())
+}
+
+/// Waits before the `attempt`th send of a chunk, not at all before the first.
+/// Sleeps with the client's sleep implementation, or Tokio's if it has none.
+async fn backoff(client: &Client, attempt: u32, base_delay: Duration) {
+    if attempt > 0
+        && let Some(sleep) = client
+            .config()
+            .sleep_impl()
+            .or_else(aws_smithy_async::rt::sleep::default_async_sleep)
+    {
+        sleep.sleep(backoff_delay(attempt, base_delay)).await;
+    }
+}
+
+/// Identifies a key whatever the order of its attributes. Key attributes are
+/// strings, numbers or binary.
+type KeyId = Vec<(String, String)>;
+
+fn key_id(key: &Item) -> KeyId {
+    let mut id = key
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Av::S(s) => format!("S{s}"),
+                Av::N(n) => format!("N{n}"),
+                Av::B(b) => format!("B{:?}", b.as_ref()),
+                other => format!("{other:?}"),
+            };
+            (name.clone(), value)
+        })
+        .collect::<KeyId>();
+    id.sort();
+    id
+}
+
+/// Groups `requests` by table, as batch requests take them.
+fn by_table<R>(requests: impl IntoIterator<Item = (String, R)>) -> HashMap<String, Vec<R>> {
+    let mut tables: HashMap<String, Vec<R>> = HashMap::new();
+    for (table, request) in requests {
+        tables.entry(table).or_default().push(request);
+    }
+    tables
+}
+
+/// Adds `more` to `requests` table by table, leaving out empty tables.
+fn extend_tables<R>(requests: &mut HashMap<String, Vec<R>>, more: HashMap<String, Vec<R>>) {
+    for (table, more) in more {
+        if !more.is_empty() {
+            requests.entry(table).or_default().extend(more);
+        }
+    }
+}
+
+/// Splits `requests` into chunks of at most `size`, each grouped by table.
+fn chunks<R>(requests: Vec<(String, R)>, size: usize) -> Vec<HashMap<String, Vec<R>>> {
+    let mut requests = requests.into_iter().peekable();
+    let mut chunks = vec![];
+    while requests.peek().is_some() {
+        chunks.push(by_table(requests.by_ref().take(size)));
+    }
+    chunks
+}
+
+//
+// ─── BATCH WRITES ───────────────────────────────────────────────────────────────
+//
+
+/// The most writes DynamoDB accepts in one `BatchWriteItem`.
+pub const MAX_BATCH_WRITE_ITEMS: usize = 25;
+
+/// Writes by table name, as `BatchWriteItem` takes them.
+pub type WriteRequests = HashMap<String, Vec<WriteRequest>>;
+
+/// The writes of a batch that did not go through.
+#[derive(Debug, Default)]
+pub struct BatchWriteReport {
+    /// Still unprocessed once the attempts ran out.
+    pub unprocessed: WriteRequests,
+    /// Chunks DynamoDB rejected outright, with the reason.
+    pub rejected: Vec<(WriteRequests, Error)>,
+    /// Writes never sent, because a later write of the batch went to the
+    /// same key.
+    pub duplicates: WriteRequests,
+}
+
+impl BatchWriteReport {
+    /// Whether every write that was sent went through. Dropped duplicates do
+    /// not count against it.
+    pub fn is_complete(&self) -> bool {
+        self.unprocessed.is_empty() && self.rejected.is_empty()
+    }
+
+    fn merge(mut self, other: BatchWriteReport) -> Self {
+        extend_tables(&mut self.unprocessed, other.unprocessed);
+        self.rejected.extend(other.rejected);
+        extend_tables(&mut self.duplicates, other.duplicates);
+        self
+    }
+}
+
+/// One put or delete of a [`BatchWriter`], of any entity. Entities convert
+/// into puts, so writes of different types can go in one `put_all`.
+pub struct BatchWrite {
+    /// The write with its table and the key it writes to, or why the entity
+    /// or key could not be rendered
+    request: Result<(Resolve, Item, WriteRequest), ItemError>,
+}
+
+impl BatchWrite {
+    pub fn put<T: Entity2>(entity: T) -> Self {
+        let request = entity.to_attribute_map().and_then(|item| {
+            let put = PutRequest::builder()
+                .set_item(Some(item))
+                .build()
+                .expect("item is set");
+            let resolve: Resolve = TableConfig::resolve::<T>;
+            Ok((
+                resolve,
+                entity.key_attributes()?,
+                WriteRequest::builder().put_request(put).build(),
+            ))
+        });
+        BatchWrite { request }
+    }
+
+    pub fn delete<K: PrimaryKey>(key: K) -> Self {
+        let request = key.to_key().map(|key| {
+            let delete = DeleteRequest::builder()
+                .set_key(Some(key.clone()))
+                .build()
+                .expect("key is set");
+            let resolve: Resolve = TableConfig::resolve::<K::Entity>;
+            (
+                resolve,
+                key,
+                WriteRequest::builder().delete_request(delete).build(),
+            )
+        });
+        BatchWrite { request }
+    }
+}
+
+impl<T: Entity2> From<T> for BatchWrite {
+    fn from(entity: T) -> Self {
+        BatchWrite::put(entity)
+    }
+}
+
+/// Puts and deletes items of any entities with `BatchWriteItem`. Entities of
+/// different types may be mixed as [`BatchWrite`]s, and may be stored in
+/// different tables.
+///
+/// DynamoDB rejects a batch that writes one key twice, so only the last write
+/// to each key is sent, and the others are reported as duplicates.
+pub struct BatchWriter {
+    client: Client,
+    writes: Vec<BatchWrite>,
+    tables: TableConfig,
+    concurrency: usize,
+    max_attempts: u32,
+    base_delay: Duration,
+}
+
+impl BatchWriter {
+    pub fn new(client: Client) -> Self {
+        BatchWriter {
+            client,
+            writes: vec![],
+            tables: TableConfig::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: DEFAULT_BASE_DELAY,
+        }
+    }
+
+    pub fn put(mut self, write: impl Into<BatchWrite>) -> Self {
+        self.writes.push(write.into());
+        self
+    }
+
+    /// Adds every write, which may be entities of one type or [`BatchWrite`]s
+    /// of several.
+    pub fn put_all(mut self, writes: impl IntoIterator<Item = impl Into<BatchWrite>>) -> Self {
+        self.writes.extend(writes.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn delete<K: PrimaryKey>(mut self, key: K) -> Self {
+        self.writes.push(BatchWrite::delete(key));
+        self
+    }
+
+    /// Resolves the table of every write through `tables` instead of the
+    /// entities' own.
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.tables = tables;
+        self
+    }
+
+    /// How many chunks are in flight at once. Defaults to 4.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// How often a chunk is sent before its unprocessed items are given up on.
+    /// Defaults to 8.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// The wait before the first retry, doubled for every retry after it.
+    /// Defaults to 50ms.
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Sends every write, returning those that did not go through. Nothing is
+    /// sent if any entity or key cannot be rendered, or the table of any write
+    /// cannot be resolved.
+    pub async fn send(self) -> Result<BatchWriteReport, Error> {
+        let mut requests = vec![];
+        for write in self.writes {
+            let (resolve, key, request) = write.request?;
+            requests.push((resolve(&self.tables)?, key, request));
+        }
+        let (requests, duplicates) = last_write_per_key(requests);
+
+        let client = &self.client;
+        let (max_attempts, base_delay) = (self.max_attempts, self.base_delay);
+        let report = futures::stream::iter(chunks(requests, MAX_BATCH_WRITE_ITEMS))
+            .map(|chunk| write_chunk(client, chunk, max_attempts, base_delay))
+            .buffer_unordered(self.concurrency)
+            .fold(
+                BatchWriteReport {
+                    duplicates,
+                    ..BatchWriteReport::default()
+                },
+                |report, chunk_report| async move { report.merge(chunk_report) },
+            )
+            .await;
+        Ok(report)
+    }
+}
+
+/// Keeps the last write to each key of each table, in the position of the
+/// first, and returns the writes it dropped by table.
+fn last_write_per_key(
+    requests: Vec<(String, Item, WriteRequest)>,
+) -> (Vec<(String, WriteRequest)>, WriteRequests) {
+    let mut positions = HashMap::new();
+    let mut kept: Vec<(String, WriteRequest)> = vec![];
+    let mut dropped = vec![];
+    for (table, key, request) in requests {
+        match positions.entry((table.clone(), key_id(&key))) {
+            Entry::Occupied(position) => {
+                let earlier = std::mem::replace(&mut kept[*position.get()], (table, request));
+                dropped.push(earlier);
+            }
+            Entry::Vacant(position) => {
+                position.insert(kept.len());
+                kept.push((table, request));
+            }
+        }
+    }
+    (kept, by_table(dropped))
+}
+
+/// Writes one chunk, resending what comes back unprocessed.
+async fn write_chunk(
+    client: &Client,
+    mut requests: WriteRequests,
+    max_attempts: u32,
+    base_delay: Duration,
+) -> BatchWriteReport {
+    for attempt in 0..max_attempts {
+        backoff(client, attempt, base_delay).await;
+        let output = match client
+            .batch_write_item()
+            .set_request_items(Some(requests.clone()))
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(err) => {
+                return BatchWriteReport {
+                    rejected: vec![(requests, err.into())],
+                    ..BatchWriteReport::default()
+                };
+            }
+        };
+        requests = HashMap::new();
+        extend_tables(&mut requests, output.unprocessed_items.unwrap_or_default());
+        if requests.is_empty() {
+            break;
+        }
+    }
+    BatchWriteReport {
+        unprocessed: requests,
+        ..BatchWriteReport::default()
+    }
+}
+
+//
+// ─── BATCH GETS ─────────────────────────────────────────────────────────────────
+//
+
+/// The most keys DynamoDB accepts in one `BatchGetItem`.
+pub const MAX_BATCH_GET_KEYS: usize = 100;
+
+/// Keys by table name, as `BatchGetItem` takes them.
+pub type TableKeys = HashMap<String, Vec<Item>>;
+
+/// What a [`BatchGetter`] found under one key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lookup<T> {
+    Found(T),
+    /// No item is stored under the key.
+    Missing,
+    /// The key was still unprocessed after the last attempt, or in a chunk
+    /// DynamoDB rejected, so whether an item exists is unknown.
+    Failed,
+}
+
+impl<T> Lookup<T> {
+    pub fn found(self) -> Option<T> {
+        match self {
+            Lookup::Found(entity) => Some(entity),
+            Lookup::Missing | Lookup::Failed => None,
+        }
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Lookup::Failed)
+    }
+}
+
+/// Keys of one entity type, added to a [`BatchGetter`] with `get_all` and then
+/// used to read their entities back out of the [`BatchGetResults`].
+pub struct BatchKeys<K> {
+    keys: Vec<K>,
+}
+
+impl<K: PrimaryKey> BatchKeys<K> {
+    pub fn new(keys: impl IntoIterator<Item = K>) -> Self {
+        BatchKeys {
+            keys: keys.into_iter().collect(),
+        }
+    }
+}
+
+/// Loads items of one or more entities by key with `BatchGetItem`.
+///
+/// Each [`BatchKeys`] added with `get_all` picks its entities out of the
+/// results, in request order or by key. A key requested more than once is
+/// only fetched once. Entities may be stored in different tables.
+pub struct BatchGetter {
+    client: Client,
+    keys: Vec<Result<(Resolve, Item), ItemError>>,
+    tables: TableConfig,
+    concurrency: usize,
+    max_attempts: u32,
+    base_delay: Duration,
+}
+
+impl BatchGetter {
+    pub fn new(client: Client) -> Self {
+        BatchGetter {
+            client,
+            keys: vec![],
+            tables: TableConfig::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: DEFAULT_BASE_DELAY,
+        }
+    }
+
+    pub fn get_all<K: PrimaryKey>(mut self, keys: &BatchKeys<K>) -> Self {
+        self.keys.extend(keys.keys.iter().map(|key| {
+            let resolve: Resolve = TableConfig::resolve::<K::Entity>;
+            Ok((resolve, key.to_key()?))
+        }));
+        self
+    }
+
+    /// Resolves the table of every key through `tables` instead of the
+    /// entities' own.
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.tables = tables;
+        self
+    }
+
+    /// How many chunks are in flight at once. Defaults to 4.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// How often a chunk is sent before its unprocessed keys are given up on.
+    /// Defaults to 8.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// The wait before the first retry, doubled for every retry after it.
+    /// Defaults to 50ms.
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Fetches every key. Nothing is sent if any key cannot be rendered or its
+    /// table cannot be resolved. Chunks DynamoDB rejects are reported in the results, and the
+    /// other chunks are still read.
+    pub async fn send(self) -> Result<BatchGetResults, Error> {
+        let mut seen = HashSet::new();
+        let mut keys = vec![];
+        for key in self.keys {
+            let (resolve, key) = key?;
+            let table = resolve(&self.tables)?;
+            if seen.insert((table.clone(), key_id(&key))) {
+                keys.push((table, key));
+            }
+        }
+
+        // Keys of different entities may use different attribute names
+        let mut key_names: HashMap<String, Vec<Vec<String>>> = HashMap::new();
+        for (table, key) in &keys {
+            let mut names = key.keys().cloned().collect::<Vec<_>>();
+            names.sort();
+            let table_names = key_names.entry(table.clone()).or_default();
+            if !table_names.contains(&names) {
+                table_names.push(names);
+            }
+        }
+
+        let client = &self.client;
+        let (max_attempts, base_delay) = (self.max_attempts, self.base_delay);
+        let Fetched {
+            items,
+            unprocessed,
+            rejected,
+        } = futures::stream::iter(chunks(keys, MAX_BATCH_GET_KEYS))
+            .map(|chunk| get_chunk(client, chunk, max_attempts, base_delay))
+            .buffer_unordered(self.concurrency)
+            .fold(Fetched::default(), |fetched, chunk_fetched| async move {
+                fetched.merge(chunk_fetched)
+            })
+            .await;
+
+        let failed = failed_keys(&unprocessed, &rejected);
+        let items = items
+            .into_iter()
+            .flat_map(|(table, items)| items.into_iter().map(move |item| (table.clone(), item)))
+            .filter_map(|(table, item)| {
+                let id = key_names.get(&table)?.iter().find_map(|names| {
+                    let key = names
+                        .iter()
+                        .map(|name| Some((name.clone(), item.get(name)?.clone())))
+                        .collect::<Option<Item>>()?;
+                    let id = (table.clone(), key_id(&key));
+                    seen.contains(&id).then_some(id)
+                })?;
+                Some((id, item))
+            })
+            .collect();
+        Ok(BatchGetResults {
+            items,
+            failed,
+            unprocessed,
+            rejected,
+            tables: self.tables,
+        })
+    }
+}
+
+/// The keys of `unprocessed` and of every rejected chunk, by table.
+fn failed_keys(
+    unprocessed: &TableKeys,
+    rejected: &[(TableKeys, Error)],
+) -> HashSet<(String, KeyId)> {
+    std::iter::once(unprocessed)
+        .chain(rejected.iter().map(|(keys, _)| keys))
+        .flatten()
+        .flat_map(|(table, keys)| keys.iter().map(|key| (table.clone(), key_id(key))))
+        .collect()
+}
+
+/// What chunks of a [`BatchGetter`] came back with, by table.
+#[derive(Default)]
+struct Fetched {
+    items: HashMap<String, Vec<Item>>,
+    unprocessed: TableKeys,
+    rejected: Vec<(TableKeys, Error)>,
+}
+
+impl Fetched {
+    fn merge(mut self, other: Fetched) -> Self {
+        extend_tables(&mut self.items, other.items);
+        extend_tables(&mut self.unprocessed, other.unprocessed);
+        self.rejected.extend(other.rejected);
+        self
+    }
+}
+
+/// Fetches one chunk, resending the keys that come back unprocessed. Items
+/// read before DynamoDB rejects a resend are kept.
+async fn get_chunk(
+    client: &Client,
+    mut keys: TableKeys,
+    max_attempts: u32,
+    base_delay: Duration,
+) -> Fetched {
+    let mut fetched = Fetched::default();
+    for attempt in 0..max_attempts {
+        backoff(client, attempt, base_delay).await;
+        let request = keys
+            .iter()
+            .map(|(table, keys)| {
+                let keys = KeysAndAttributes::builder()
+                    .set_keys(Some(keys.clone()))
+                    .build()
+                    .expect("keys are set");
+                (table.clone(), keys)
+            })
+            .collect();
+        let output = match client
+            .batch_get_item()
+            .set_request_items(Some(request))
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(err) => {
+                fetched.rejected.push((keys, err.into()));
+                return fetched;
+            }
+        };
+        extend_tables(&mut fetched.items, output.responses.unwrap_or_default());
+        keys = HashMap::new();
+        extend_tables(
+            &mut keys,
+            output
+                .unprocessed_keys
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(table, unprocessed)| (table, unprocessed.keys))
+                .collect(),
+        );
+        if keys.is_empty() {
+            break;
+        }
+    }
+    fetched.unprocessed = keys;
+    fetched
+}
+
+/// The items a [`BatchGetter`] found.
+#[derive(Debug)]
+pub struct BatchGetResults {
+    items: HashMap<(String, KeyId), Item>,
+    /// The keys of `unprocessed` and `rejected`
+    failed: HashSet<(String, KeyId)>,
+    /// Keys still unprocessed once the attempts ran out, by table. They read
+    /// as [`Lookup::Failed`].
+    pub unprocessed: TableKeys,
+    /// Keys of chunks DynamoDB rejected outright, with the reason. They read
+    /// as [`Lookup::Failed`] too.
+    pub rejected: Vec<(TableKeys, Error)>,
+    tables: TableConfig,
+}
+
+impl BatchGetResults {
+    /// Whether every key was looked up.
+    pub fn is_complete(&self) -> bool {
+        self.unprocessed.is_empty() && self.rejected.is_empty()
+    }
+
+    /// What was found under each of `keys`, in the order they were added.
+    pub fn in_order<K: PrimaryKey>(
+        &self,
+        keys: BatchKeys<K>,
+    ) -> Result<Vec<Lookup<K::Entity>>, Error> {
+        keys.keys.iter().map(|key| self.entity(key)).collect()
+    }
+
+    /// The entities of `keys` that exist, and the keys that failed, looked up
+    /// by their key.
+    pub fn by_key<K: PrimaryKey>(&self, keys: BatchKeys<K>) -> Result<EntitiesByKey<K>, Error> {
+        let mut entities = HashMap::new();
+        let mut failed = HashMap::new();
+        for key in keys.keys {
+            let id = key_id(&key.to_key()?);
+            match self.entity(&key)? {
+                Lookup::Found(entity) => {
+                    entities.insert(id, (key, entity));
+                }
+                Lookup::Failed => {
+                    failed.insert(id, key);
+                }
+                Lookup::Missing => {}
+            }
+        }
+        Ok(EntitiesByKey { entities, failed })
+    }
+
+    fn entity<K: PrimaryKey>(&self, key: &K) -> Result<Lookup<K::Entity>, Error> {
+        let id = (self.tables.resolve::<K::Entity>()?, key_id(&key.to_key()?));
+        match self.items.get(&id) {
+            Some(item) => Ok(Lookup::Found(K::Entity::from_attribute_map(item)?)),
+            None if self.failed.contains(&id) => Ok(Lookup::Failed),
+            None => Ok(Lookup::Missing),
+        }
+    }
+}
+
+/// Entities found by a [`BatchGetter`], by the typed key they were requested
+/// with.
+pub struct EntitiesByKey<K: PrimaryKey> {
+    entities: HashMap<KeyId, (K, K::Entity)>,
+    failed: HashMap<KeyId, K>,
+}
+
+impl<K: PrimaryKey> EntitiesByKey<K> {
+    pub fn get(&self, key: &K) -> Lookup<&K::Entity> {
+        let Ok(key) = key.to_key() else {
+            return Lookup::Missing;
+        };
+        let id = key_id(&key);
+        match self.entities.get(&id) {
+            Some((_, entity)) => Lookup::Found(entity),
+            None if self.failed.contains_key(&id) => Lookup::Failed,
+            None => Lookup::Missing,
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Lookup<K::Entity> {
+        let Ok(key) = key.to_key() else {
+            return Lookup::Missing;
+        };
+        let id = key_id(&key);
+        match self.entities.remove(&id) {
+            Some((_, entity)) => Lookup::Found(entity),
+            None if self.failed.contains_key(&id) => Lookup::Failed,
+            None => Lookup::Missing,
+        }
+    }
+
+    /// The keys whose lookup failed, see [`Lookup::Failed`].
+    pub fn failed(&self) -> impl Iterator<Item = &K> {
+        self.failed.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+/// The entities found, with their keys.
+impl<K: PrimaryKey> IntoIterator for EntitiesByKey<K> {
+    type Item = (K, K::Entity);
+    type IntoIter = std::collections::hash_map::IntoValues<KeyId, (K, K::Entity)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entities.into_values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CompositeAttributeValue, DEFAULT_DELIMITER, KeyDef, ScalarType, SchemaV2, Segment,
+        SegmentEncoding, attribute_field_value, segment_value, serialize_attributes,
+    };
+
+    fn key(id: u32) -> Item {
+        Item::from([("pk".to_string(), Av::S(format!("p#{id}")))])
+    }
+
+    /// A put of item `id`, told apart from other puts to it by `revision`
+    fn put(table: &str, id: u32, revision: u32) -> (String, Item, WriteRequest) {
+        let mut item = key(id);
+        item.insert("revision".to_string(), Av::N(revision.to_string()));
+        let put = PutRequest::builder().set_item(Some(item)).build().unwrap();
+        (
+            table.to_string(),
+            key(id),
+            WriteRequest::builder().put_request(put).build(),
+        )
+    }
+
+    fn revisions(requests: &[WriteRequest]) -> Vec<(String, String)> {
+        requests
+            .iter()
+            .map(|request| {
+                let item = request.put_request().unwrap().item();
+                let field = |name: &str| match &item[name] {
+                    Av::S(value) | Av::N(value) => value.clone(),
+                    other => panic!("{other:?}"),
+                };
+                (field("pk"), field("revision"))
+            })
+            .collect()
+    }
+
+    fn report(table: &str, id: u32) -> WriteRequests {
+        let (table, _, request) = put(table, id, 0);
+        HashMap::from([(table, vec![request])])
+    }
+
+    #[test]
+    fn writes_go_out_in_chunks_of_at_most_25() {
+        let requests = (0..60)
+            .map(|id| {
+                let (table, _, request) = put(["posts", "counts"][id as usize % 2], id, 0);
+                (table, request)
+            })
+            .collect();
+        let sizes = chunks(requests, MAX_BATCH_WRITE_ITEMS)
+            .iter()
+            .map(|chunk| chunk.values().map(Vec::len).sum::<usize>())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![25, 25, 10]);
+    }
+
+    #[test]
+    fn the_last_write_to_a_key_wins_and_the_others_are_reported() {
+        let (kept, dropped) = last_write_per_key(vec![
+            put("posts", 1, 1),
+            put("posts", 2, 1),
+            put("counts", 1, 1),
+            put("posts", 1, 2),
+            put("posts", 1, 3),
+        ]);
+        let (tables, requests): (Vec<_>, Vec<_>) = kept.into_iter().unzip();
+        assert_eq!(tables, vec!["posts", "posts", "counts"]);
+        assert_eq!(
+            revisions(&requests),
+            vec![
+                ("p#1".to_string(), "3".to_string()),
+                ("p#2".to_string(), "1".to_string()),
+                ("p#1".to_string(), "1".to_string()),
+            ]
+        );
+        assert_eq!(dropped.keys().collect::<Vec<_>>(), vec!["posts"]);
+        assert_eq!(
+            revisions(&dropped["posts"]),
+            vec![
+                ("p#1".to_string(), "1".to_string()),
+                ("p#1".to_string(), "2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_merge_table_by_table() {
+        let first = BatchWriteReport {
+            unprocessed: report("posts", 1),
+            duplicates: report("posts", 2),
+            ..BatchWriteReport::default()
+        };
+        let second = BatchWriteReport {
+            unprocessed: report("posts", 3),
+            rejected: vec![(
+                report("counts", 4),
+                Error::InvalidRequest("throttled".to_string()),
+            )],
+            duplicates: report("counts", 5),
+        };
+        let merged = first.merge(second);
+        assert_eq!(merged.unprocessed["posts"].len(), 2);
+        assert_eq!(merged.rejected.len(), 1);
+        assert_eq!(merged.duplicates.len(), 2);
+        assert!(!merged.is_complete());
+
+        let only_duplicates = BatchWriteReport {
+            duplicates: report("posts", 2),
+            ..BatchWriteReport::default()
+        };
+        assert!(
+            BatchWriteReport::default()
+                .merge(only_duplicates)
+                .is_complete()
+        );
+    }
+
+    #[test]
+    fn backoff_is_jittered_below_the_doubled_delay() {
+        let base = Duration::from_millis(50);
+        let delays = (0..100).map(|_| backoff_delay(4, base)).collect::<Vec<_>>();
+        assert!(delays.iter().all(|delay| *delay <= base * 8));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+        assert!((0..100).all(|_| backoff_delay(1, base) <= base));
+    }
+
+    /// An entity stored under `p#{id}`, as `#[derive(Dynodmize)]` writes it
+    #[derive(Debug, PartialEq, serde::Serialize)]
+    struct Post {
+        id: u32,
+    }
+
+    struct PostKey {
+        id: u32,
+    }
+
+    impl Entity2 for Post {
+        fn get_schema() -> SchemaV2 {
+            SchemaV2 {
+                partition_key_def: KeyDef {
+                    attribute_name: "pk".to_string(),
+                    attribute_value: CompositeAttributeValue {
+                        segments: vec![Segment {
+                            struct_field_name: "id".to_string(),
+                            prefix: Some("p".to_string()),
+                            encoding: SegmentEncoding::Plain,
+                            format: None,
+                            optional: false,
+                            scalar_type: ScalarType::S,
+                        }],
+                        prefix: None,
+                        suffix: None,
+                    },
+                },
+                sort_key_def: None,
+                non_key_defs: vec![],
+                index_defs: vec![],
+                delimiter: DEFAULT_DELIMITER,
+                version_attribute: None,
+                table_name: Some("posts".to_string()),
+            }
+        }
+
+        fn segment_values(&self) -> Result<HashMap<String, String>, ItemError> {
+            Ok(HashMap::from([("id".to_string(), self.id.to_string())]))
+        }
+
+        fn segment_value(field: &str, value: &Av) -> Result<String, ItemError> {
+            Ok(attribute_field_value::<u32>(value, field)?.to_string())
+        }
+
+        fn field_attributes(&self) -> Item {
+            serialize_attributes(self)
+        }
+
+        fn from_item(item: &serde_json::Value) -> Result<Self, ItemError> {
+            let segments = Self::get_schema().decode_segments(item)?;
+            Ok(Post {
+                id: segment_value(&segments, "id")?,
+            })
+        }
+    }
+
+    impl PrimaryKey for PostKey {
+        type Entity = Post;
+
+        fn segment_values(&self) -> Result<HashMap<String, String>, ItemError> {
+            Ok(HashMap::from([("id".to_string(), self.id.to_string())]))
+        }
+
+        fn field_attributes(&self) -> Item {
+            Item::from([("id".to_string(), Av::N(self.id.to_string()))])
+        }
+    }
+
+    /// Post 1 found, post 2 missing, post 3 unprocessed and post 4 rejected
+    fn results() -> BatchGetResults {
+        let posts = |ids: &[u32]| {
+            HashMap::from([("posts".to_string(), ids.iter().map(|id| key(*id)).collect())])
+        };
+        let unprocessed = posts(&[3]);
+        let rejected = vec![(posts(&[4]), Error::InvalidRequest("throttled".to_string()))];
+        BatchGetResults {
+            items: HashMap::from([(
+                ("posts".to_string(), key_id(&key(1))),
+                Post { id: 1 }.to_attribute_map().unwrap(),
+            )]),
+            failed: failed_keys(&unprocessed, &rejected),
+            unprocessed,
+            rejected,
+            tables: TableConfig::default(),
+        }
+    }
+
+    fn post_keys() -> BatchKeys<PostKey> {
+        BatchKeys::new([1, 2, 3, 4].map(|id| PostKey { id }))
+    }
+
+    #[test]
+    fn unprocessed_and_rejected_keys_read_as_failed_in_order() {
+        assert_eq!(
+            results().in_order(post_keys()).unwrap(),
+            vec![
+                Lookup::Found(Post { id: 1 }),
+                Lookup::Missing,
+                Lookup::Failed,
+                Lookup::Failed,
+            ]
+        );
+    }
+
+    #[test]
+    fn unprocessed_and_rejected_keys_read_as_failed_by_key() {
+        let mut posts = results().by_key(post_keys()).unwrap();
+        assert_eq!(
+            posts.get(&PostKey { id: 1 }),
+            Lookup::Found(&Post { id: 1 })
+        );
+        assert_eq!(posts.get(&PostKey { id: 2 }), Lookup::Missing);
+        assert_eq!(posts.get(&PostKey { id: 3 }), Lookup::Failed);
+        assert_eq!(posts.remove(&PostKey { id: 4 }), Lookup::Failed);
+        let mut failed = posts.failed().map(|key| key.id).collect::<Vec<_>>();
+        failed.sort();
+        assert_eq!(failed, vec![3, 4]);
+        assert_eq!(posts.len(), 1);
+    }
+}
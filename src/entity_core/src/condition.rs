@@ -0,0 +1,388 @@
+//! Condition expressions for conditional writes.
+//!
+//! Conditions are typed by the entity they apply to. Generated `<Name>Fields`
+//! hand out a [`Field`] per struct field, and placeholders are named when the
+//! request is built.
+
+use crate::{Item, SchemaV2, serialize_attribute};
+use aws_sdk_dynamodb::types::AttributeValue as Av;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn operator(self) -> &'static str {
+        match self {
+            Comparison::Eq => "=",
+            Comparison::Ne => "<>",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    /// The item itself, checked through its partition key
+    ItemExists,
+    AttributeExists(String),
+    Compare(String, Comparison, Av),
+    Between(String, Av, Av),
+    BeginsWith(String, String),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+}
+
+/// A `ConditionExpression` on an item of `T`.
+pub struct Condition<T> {
+    node: Node,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Condition<T> {
+    fn clone(&self) -> Self {
+        Condition::from_node(self.node.clone())
+    }
+}
+
+impl<T> std::fmt::Debug for Condition<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+impl<T> Condition<T> {
+    fn from_node(node: Node) -> Self {
+        Condition {
+            node,
+            _marker: PhantomData,
+        }
+    }
+
+    /// An item is already stored under the key.
+    pub fn item_exists() -> Self {
+        Self::from_node(Node::ItemExists)
+    }
+
+    /// Nothing is stored under the key yet.
+    pub fn item_not_exists() -> Self {
+        !Self::item_exists()
+    }
+
+    /// Any attribute by name, including derived ones such as `pk`.
+    pub fn attribute_exists(attribute: &str) -> Self {
+        Self::from_node(Node::AttributeExists(attribute.to_owned()))
+    }
+
+    pub fn attribute_not_exists(attribute: &str) -> Self {
+        !Self::attribute_exists(attribute)
+    }
+
+    pub fn and(self, other: Self) -> Self {
+        Self::from_node(Node::And(Box::new(self.node), Box::new(other.node)))
+    }
+
+    pub fn or(self, other: Self) -> Self {
+        Self::from_node(Node::Or(Box::new(self.node), Box::new(other.node)))
+    }
+
+    /// The stored item is at `version`.
+    pub(crate) fn version_is(attribute: &str, version: u64) -> Self {
+        Self::from_node(Node::Compare(
+            attribute.to_owned(),
+            Comparison::Eq,
+            Av::N(version.to_string()),
+        ))
+    }
+
+    /// The expression, with its names and values added under fresh `#cN` and
+    /// `:cN` placeholders.
+    pub(crate) fn expression(
+        &self,
+        schema: &SchemaV2,
+        names: &mut HashMap<String, String>,
+        values: &mut Item,
+    ) -> String {
+        render(&self.node, schema, names, values)
+    }
+}
+
+impl<T> std::ops::Not for Condition<T> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self::from_node(Node::Not(Box::new(self.node)))
+    }
+}
+
+fn render(
+    node: &Node,
+    schema: &SchemaV2,
+    names: &mut HashMap<String, String>,
+    values: &mut Item,
+) -> String {
+    let mut name = |attribute: &str| {
+        let placeholder = format!("#c{}", names.len());
+        names.insert(placeholder.clone(), attribute.to_owned());
+        placeholder
+    };
+    match node {
+        Node::ItemExists => format!(
+            "attribute_exists({})",
+            name(&schema.partition_key_def.attribute_name)
+        ),
+        Node::AttributeExists(attribute) => format!("attribute_exists({})", name(attribute)),
+        // `NOT attribute_exists(...)` is written the way DynamoDB documents it
+        Node::Not(inner) => match inner.as_ref() {
+            Node::ItemExists => format!(
+                "attribute_not_exists({})",
+                name(&schema.partition_key_def.attribute_name)
+            ),
+            Node::AttributeExists(attribute) => {
+                format!("attribute_not_exists({})", name(attribute))
+            }
+            inner => format!("(NOT {})", render(inner, schema, names, values)),
+        },
+        Node::Compare(attribute, comparison, value) => {
+            let attribute = name(attribute);
+            let value = bind(values, value.clone());
+            format!("{attribute} {} {value}", comparison.operator())
+        }
+        Node::Between(attribute, low, high) => {
+            let attribute = name(attribute);
+            let low = bind(values, low.clone());
+            let high = bind(values, high.clone());
+            format!("{attribute} BETWEEN {low} AND {high}")
+        }
+        Node::BeginsWith(attribute, prefix) => {
+            let attribute = name(attribute);
+            let prefix = bind(values, Av::S(prefix.clone()));
+            format!("begins_with({attribute}, {prefix})")
+        }
+        Node::And(left, right) => format!(
+            "({} AND {})",
+            render(left, schema, names, values),
+            render(right, schema, names, values)
+        ),
+        Node::Or(left, right) => format!(
+            "({} OR {})",
+            render(left, schema, names, values),
+            render(right, schema, names, values)
+        ),
+    }
+}
+
+fn bind(values: &mut Item, value: Av) -> String {
+    let placeholder = format!(":c{}", values.len());
+    values.insert(placeholder.clone(), value);
+    placeholder
+}
+
+/// A struct field of `T` holding a `V`, for building conditions on it.
+pub struct Field<T, V> {
+    attribute: &'static str,
+    _marker: PhantomData<fn() -> (T, V)>,
+}
+
+impl<T, V: Serialize> Field<T, V> {
+    pub const fn new(attribute: &'static str) -> Self {
+        Field {
+            attribute,
+            _marker: PhantomData,
+        }
+    }
+
+    fn compare(&self, comparison: Comparison, value: &V) -> Condition<T> {
+        Condition::from_node(Node::Compare(
+            self.attribute.to_owned(),
+            comparison,
+            serialize_attribute(value),
+        ))
+    }
+
+    pub fn exists(&self) -> Condition<T> {
+        Condition::attribute_exists(self.attribute)
+    }
+
+    pub fn not_exists(&self) -> Condition<T> {
+        Condition::attribute_not_exists(self.attribute)
+    }
+
+    pub fn eq(&self, value: V) -> Condition<T> {
+        self.compare(Comparison::Eq, &value)
+    }
+
+    pub fn ne(&self, value: V) -> Condition<T> {
+        self.compare(Comparison::Ne, &value)
+    }
+
+    pub fn lt(&self, value: V) -> Condition<T> {
+        self.compare(Comparison::Lt, &value)
+    }
+
+    pub fn le(&self, value: V) -> Condition<T> {
+        self.compare(Comparison::Le, &value)
+    }
+
+    pub fn gt(&self, value: V) -> Condition<T> {
+        self.compare(Comparison::Gt, &value)
+    }
+
+    pub fn ge(&self, value: V) -> Condition<T> {
+        self.compare(Comparison::Ge, &value)
+    }
+
+    /// Both ends are inclusive.
+    pub fn between(&self, low: V, high: V) -> Condition<T> {
+        Condition::from_node(Node::Between(
+            self.attribute.to_owned(),
+            serialize_attribute(&low),
+            serialize_attribute(&high),
+        ))
+    }
+}
+
+impl<T> Field<T, String> {
+    pub fn begins_with(&self, prefix: &str) -> Condition<T> {
+        Condition::from_node(Node::BeginsWith(
+            self.attribute.to_owned(),
+            prefix.to_owned(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompositeAttributeValue, DEFAULT_DELIMITER, KeyDef};
+
+    struct Account;
+
+    const BALANCE: Field<Account, u64> = Field::new("balance");
+    const NAME: Field<Account, String> = Field::new("name");
+    const STATUS: Field<Account, String> = Field::new("status");
+
+    fn schema() -> SchemaV2 {
+        SchemaV2 {
+            partition_key_def: KeyDef {
+                attribute_name: "pk".to_string(),
+                attribute_value: CompositeAttributeValue {
+                    segments: vec![],
+                    prefix: None,
+                    suffix: None,
+                },
+            },
+            sort_key_def: None,
+            non_key_defs: vec![],
+            index_defs: vec![],
+            delimiter: DEFAULT_DELIMITER,
+            version_attribute: None,
+            table_name: None,
+        }
+    }
+
+    fn rendered(condition: Condition<Account>) -> (String, HashMap<String, String>, Item) {
+        let mut names = HashMap::new();
+        let mut values = Item::new();
+        let expression = condition.expression(&schema(), &mut names, &mut values);
+        (expression, names, values)
+    }
+
+    fn namesof(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(placeholder, name)| (placeholder.to_string(), name.to_string()))
+            .collect()
+    }
+
+    fn n(value: &str) -> Av {
+        Av::N(value.to_string())
+    }
+
+    #[test]
+    fn renders_each_comparison_with_its_operator() {
+        let cases = [
+            (BALANCE.eq(1), "#c0 = :c0"),
+            (BALANCE.ne(1), "#c0 <> :c0"),
+            (BALANCE.lt(1), "#c0 < :c0"),
+            (BALANCE.le(1), "#c0 <= :c0"),
+            (BALANCE.gt(1), "#c0 > :c0"),
+            (BALANCE.ge(1), "#c0 >= :c0"),
+        ];
+        for (condition, expected) in cases {
+            let (expression, names, values) = rendered(condition);
+            assert_eq!(expression, expected);
+            assert_eq!(names, namesof(&[("#c0", "balance")]));
+            assert_eq!(values, Item::from([(":c0".to_string(), n("1"))]));
+        }
+    }
+
+    #[test]
+    fn renders_nested_conditions_with_placeholders_in_order() {
+        let condition = BALANCE
+            .between(10, 20)
+            .and(NAME.begins_with("A").or(STATUS.exists()))
+            .and(STATUS.ne("closed".to_string()));
+        let (expression, names, values) = rendered(condition);
+        assert_eq!(
+            expression,
+            "((#c0 BETWEEN :c0 AND :c1 AND (begins_with(#c1, :c2) OR attribute_exists(#c2))) \
+             AND #c3 <> :c3)"
+        );
+        assert_eq!(
+            names,
+            namesof(&[
+                ("#c0", "balance"),
+                ("#c1", "name"),
+                ("#c2", "status"),
+                ("#c3", "status"),
+            ])
+        );
+        assert_eq!(
+            values,
+            Item::from([
+                (":c0".to_string(), n("10")),
+                (":c1".to_string(), n("20")),
+                (":c2".to_string(), Av::S("A".to_string())),
+                (":c3".to_string(), Av::S("closed".to_string())),
+            ])
+        );
+    }
+
+    #[test]
+    fn numbering_continues_after_placeholders_already_taken() {
+        let mut names = namesof(&[("#c0", "version")]);
+        let mut values = Item::from([(":c0".to_string(), n("3"))]);
+        let expression = BALANCE.gt(0).expression(&schema(), &mut names, &mut values);
+        assert_eq!(expression, "#c1 > :c1");
+        assert_eq!(names["#c1"], "balance");
+        assert_eq!(values[":c1"], n("0"));
+    }
+
+    #[test]
+    fn negated_existence_is_written_as_attribute_not_exists() {
+        assert_eq!(
+            rendered(!Condition::item_exists()),
+            rendered(Condition::item_not_exists())
+        );
+        let (expression, names, _) = rendered(Condition::item_not_exists());
+        assert_eq!(expression, "attribute_not_exists(#c0)");
+        assert_eq!(names, namesof(&[("#c0", "pk")]));
+
+        assert_eq!(rendered(!STATUS.exists()).0, "attribute_not_exists(#c0)");
+        assert_eq!(rendered(!BALANCE.eq(1)).0, "(NOT #c0 = :c0)");
+    }
+}
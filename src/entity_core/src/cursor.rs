@@ -0,0 +1,417 @@
+//! Page tokens for handing a `LastEvaluatedKey` to API clients.
+//!
+//! A cursor is the key as URL-safe base64 JSON, optionally followed by an HMAC
+//! over it when the `signed-cursors` feature is enabled. Decoding checks the key
+//! against the entity's schema, so a token from another entity is rejected.
+
+use crate::{AttributeValue, Item, ScalarType, SchemaV2};
+use aws_sdk_dynamodb::types::AttributeValue as Av;
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+#[cfg(feature = "signed-cursors")]
+use hmac::Mac;
+use serde_json::{Map, Value};
+use std::fmt::Display;
+
+/// Separates the payload from the signature of a signed cursor.
+#[cfg(feature = "signed-cursors")]
+const SIGNATURE_SEPARATOR: char = '.';
+
+#[derive(Debug)]
+pub enum CursorError {
+    /// The token is not a cursor at all.
+    Malformed(String),
+    /// The key inside the cursor does not belong to the entity's table.
+    Schema(String),
+    /// The signature is missing or does not match.
+    Signature,
+}
+
+impl Display for CursorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CursorError::Malformed(message) => write!(f, "malformed cursor: {message}"),
+            CursorError::Schema(message) => {
+                write!(f, "cursor does not match the schema: {message}")
+            }
+            CursorError::Signature => write!(f, "cursor signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for CursorError {}
+
+/// Where the next page of a query or scan starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor(Item);
+
+impl Cursor {
+    pub fn new(key: Item) -> Self {
+        Cursor(key)
+    }
+
+    pub fn key(&self) -> &Item {
+        &self.0
+    }
+
+    pub fn into_key(self) -> Item {
+        self.0
+    }
+
+    /// The key as a URL-safe string.
+    pub fn encode(&self) -> String {
+        let attributes = self
+            .0
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), key_value_to_json(value)?)))
+            .collect::<Map<_, _>>();
+        URL_SAFE_NO_PAD.encode(Value::Object(attributes).to_string())
+    }
+
+    /// Reads a token made by [`Cursor::encode`], accepting it only if it is a
+    /// key of the table or one of its indexes.
+    pub fn decode(token: &str, schema: &SchemaV2) -> Result<Self, CursorError> {
+        let json = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|err| CursorError::Malformed(err.to_string()))?;
+        let attributes = match serde_json::from_slice(&json) {
+            Ok(Value::Object(attributes)) => attributes,
+            Ok(_) => return Err(CursorError::Malformed("expected an object".to_string())),
+            Err(err) => return Err(CursorError::Malformed(err.to_string())),
+        };
+        let key = attributes
+            .into_iter()
+            .map(|(name, value)| {
+                let value = json_to_key_value(&value).ok_or_else(|| {
+                    CursorError::Malformed(format!("attribute `{name}` is not a key value"))
+                })?;
+                Ok((name, value))
+            })
+            .collect::<Result<Item, _>>()?;
+        validate_key(&key, schema)?;
+        Ok(Cursor(key))
+    }
+
+    /// Like [`Cursor::encode`], with an HMAC-SHA256 of the payload appended.
+    #[cfg(feature = "signed-cursors")]
+    pub fn encode_signed(&self, secret: &[u8]) -> String {
+        let payload = self.encode();
+        let signature = URL_SAFE_NO_PAD.encode(signer(secret, &payload).finalize().into_bytes());
+        format!("{payload}{SIGNATURE_SEPARATOR}{signature}")
+    }
+
+    /// Reads a token made by [`Cursor::encode_signed`] with the same `secret`.
+    #[cfg(feature = "signed-cursors")]
+    pub fn decode_signed(
+        token: &str,
+        schema: &SchemaV2,
+        secret: &[u8],
+    ) -> Result<Self, CursorError> {
+        let (payload, signature) = token
+            .split_once(SIGNATURE_SEPARATOR)
+            .ok_or(CursorError::Signature)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| CursorError::Signature)?;
+        signer(secret, payload)
+            .verify_slice(&signature)
+            .map_err(|_| CursorError::Signature)?;
+        Self::decode(payload, schema)
+    }
+}
+
+impl From<Cursor> for Item {
+    fn from(cursor: Cursor) -> Self {
+        cursor.0
+    }
+}
+
+#[cfg(feature = "signed-cursors")]
+fn signer(secret: &[u8], payload: &str) -> hmac::Hmac<sha2::Sha256> {
+    let mut mac =
+        hmac::Hmac::<sha2::Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Key attributes can only be strings, numbers or binary.
+fn key_value_to_json(value: &Av) -> Option<Value> {
+    let (tag, value) = match value {
+        Av::S(s) => ("S", s.clone()),
+        Av::N(n) => ("N", n.clone()),
+        Av::B(b) => ("B", STANDARD.encode(b.as_ref())),
+        _ => return None,
+    };
+    Some(Value::Object(Map::from_iter([(
+        tag.to_string(),
+        Value::String(value),
+    )])))
+}
+
+fn json_to_key_value(value: &Value) -> Option<Av> {
+    let Value::Object(tagged) = value else {
+        return None;
+    };
+    if tagged.len() != 1 {
+        return None;
+    }
+    let (tag, value) = tagged.iter().next()?;
+    let value = value.as_str()?;
+    match tag.as_str() {
+        "S" => Some(Av::S(value.to_string())),
+        "N" => value
+            .parse::<f64>()
+            .is_ok()
+            .then(|| Av::N(value.to_string())),
+        "B" => STANDARD
+            .decode(value)
+            .ok()
+            .map(|bytes| Av::B(aws_sdk_dynamodb::primitives::Blob::new(bytes))),
+        _ => None,
+    }
+}
+
+/// A `LastEvaluatedKey` holds the table keys, plus the index keys when an index
+/// was read, and nothing else. Each has the type the schema writes it as, and
+/// composite keys parse with the schema's layout.
+fn validate_key(key: &Item, schema: &SchemaV2) -> Result<(), CursorError> {
+    let index_key_names = schema.index_defs.iter().flat_map(|index| {
+        std::iter::once(index.partition_key_def.attribute_name.as_str()).chain(
+            index
+                .sort_key_def
+                .iter()
+                .map(|key_def| key_def.attribute_name.as_str()),
+        )
+    });
+    let known = schema
+        .key_attribute_names()
+        .chain(index_key_names)
+        .collect::<Vec<_>>();
+    if let Some(name) = key.keys().find(|name| !known.contains(&name.as_str())) {
+        return Err(CursorError::Schema(format!(
+            "`{name}` is not a key attribute"
+        )));
+    }
+    if let Some(name) = schema
+        .key_attribute_names()
+        .find(|name| !key.contains_key(*name))
+    {
+        return Err(CursorError::Schema(format!("missing `{name}`")));
+    }
+
+    // Each attribute must also be one this entity could have written
+    let mut composites = vec![(
+        schema.partition_key_def.attribute_name.as_str(),
+        &schema.partition_key_def.attribute_value,
+    )];
+    let sort_keys = schema.sort_key_def.iter().chain(
+        schema
+            .index_defs
+            .iter()
+            .flat_map(|index| index.sort_key_def.iter()),
+    );
+    for index in &schema.index_defs {
+        composites.push((
+            index.partition_key_def.attribute_name.as_str(),
+            &index.partition_key_def.attribute_value,
+        ));
+    }
+    for key_def in sort_keys {
+        let name = key_def.attribute_name.as_str();
+        match &key_def.attribute_value {
+            AttributeValue::Composite(composite) => composites.push((name, composite)),
+            AttributeValue::Static(expected) => match key.get(name) {
+                Some(Av::S(value)) if value == expected => {}
+                Some(_) => {
+                    return Err(CursorError::Schema(format!("`{name}` is not `{expected}`")));
+                }
+                None => {}
+            },
+        }
+    }
+    for (name, composite) in composites {
+        let Some(value) = key.get(name) else {
+            continue;
+        };
+        let expected = composite.scalar_type();
+        let parses = match (value, expected) {
+            (Av::S(value), ScalarType::S) => {
+                composite.bare_field().is_some()
+                    || composite.segments.is_empty()
+                    || composite.parse(value, schema.delimiter).is_some()
+            }
+            (Av::N(_), ScalarType::N) | (Av::B(_), ScalarType::B) => true,
+            _ => {
+                return Err(CursorError::Schema(format!(
+                    "`{name}` is not of type {expected:?}"
+                )));
+            }
+        };
+        if !parses {
+            return Err(CursorError::Schema(format!(
+                "`{name}` is not a key of this entity"
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CompositeAttributeValue, DEFAULT_DELIMITER, IndexDef, IndexKind, KeyDef, Segment,
+        SegmentEncoding,
+    };
+
+    fn composite(
+        field: &str,
+        prefix: Option<&str>,
+        scalar_type: ScalarType,
+    ) -> KeyDef<CompositeAttributeValue> {
+        KeyDef {
+            attribute_name: field.to_string(),
+            attribute_value: CompositeAttributeValue {
+                segments: vec![Segment {
+                    struct_field_name: field.to_string(),
+                    prefix: prefix.map(str::to_string),
+                    encoding: SegmentEncoding::Plain,
+                    format: None,
+                    optional: false,
+                    scalar_type,
+                }],
+                prefix: None,
+                suffix: None,
+            },
+        }
+    }
+
+    /// Posts under `u#{user_id}` and `p#{post_id}`, with an index of each
+    /// author's posts by their bare numeric `likes`.
+    fn timeline() -> SchemaV2 {
+        let sk = composite("post_id", Some("p"), ScalarType::S);
+        let likes = composite("likes", None, ScalarType::N);
+        SchemaV2 {
+            partition_key_def: KeyDef {
+                attribute_name: "pk".to_string(),
+                ..composite("user_id", Some("u"), ScalarType::S)
+            },
+            sort_key_def: Some(KeyDef {
+                attribute_name: "sk".to_string(),
+                attribute_value: AttributeValue::Composite(sk.attribute_value),
+            }),
+            non_key_defs: vec![],
+            index_defs: vec![IndexDef {
+                index_name: "by_likes".to_string(),
+                kind: IndexKind::Global,
+                partition_key_def: composite("author", Some("a"), ScalarType::S),
+                sort_key_def: Some(KeyDef {
+                    attribute_name: likes.attribute_name,
+                    attribute_value: AttributeValue::Composite(likes.attribute_value),
+                }),
+            }],
+            delimiter: DEFAULT_DELIMITER,
+            version_attribute: None,
+            table_name: None,
+        }
+    }
+
+    fn key(attributes: &[(&str, Av)]) -> Item {
+        attributes
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    fn s(value: &str) -> Av {
+        Av::S(value.to_string())
+    }
+
+    fn index_key() -> Item {
+        key(&[
+            ("pk", s("u#1")),
+            ("sk", s("p#2")),
+            ("author", s("a#1")),
+            ("likes", Av::N("7".to_string())),
+        ])
+    }
+
+    fn decode(key: Item) -> Result<Cursor, CursorError> {
+        Cursor::decode(&Cursor::new(key).encode(), &timeline())
+    }
+
+    #[test]
+    fn decode_reads_back_table_and_index_keys() {
+        let table_key = key(&[("pk", s("u#1")), ("sk", s("p#2"))]);
+        assert_eq!(decode(table_key.clone()).unwrap().into_key(), table_key);
+        assert_eq!(decode(index_key()).unwrap().into_key(), index_key());
+    }
+
+    #[test]
+    fn decode_rejects_tokens_that_are_not_cursors() {
+        for token in [
+            "not base64!",
+            &URL_SAFE_NO_PAD.encode("[]"),
+            &URL_SAFE_NO_PAD.encode(r#"{"pk":{"N":"x"}}"#),
+        ] {
+            assert!(
+                matches!(
+                    Cursor::decode(token, &timeline()),
+                    Err(CursorError::Malformed(_))
+                ),
+                "{token}"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_rejects_key_attributes_of_the_wrong_type() {
+        let mut numeric_pk = index_key();
+        numeric_pk.insert("pk".to_string(), Av::N("1".to_string()));
+        let mut string_likes = index_key();
+        string_likes.insert("likes".to_string(), s("7"));
+        for tampered in [numeric_pk, string_likes] {
+            assert!(matches!(decode(tampered), Err(CursorError::Schema(_))));
+        }
+    }
+
+    #[test]
+    fn decode_rejects_a_tampered_sort_key() {
+        let tampered = key(&[("pk", s("u#1")), ("sk", s("c#2"))]);
+        assert!(matches!(decode(tampered), Err(CursorError::Schema(_))));
+    }
+
+    #[test]
+    fn decode_rejects_a_cursor_of_another_entity() {
+        let order = key(&[("pk", s("o#1")), ("sk", s("p#2"))]);
+        let profile = key(&[("pk", s("u#1")), ("sk", s("p#2")), ("email", s("a@b.c"))]);
+        let missing_sk = key(&[("pk", s("u#1"))]);
+        for foreign in [order, profile, missing_sk] {
+            assert!(matches!(decode(foreign), Err(CursorError::Schema(_))));
+        }
+    }
+
+    #[cfg(feature = "signed-cursors")]
+    #[test]
+    fn signed_cursors_only_decode_untouched_with_the_same_secret() {
+        let cursor = Cursor::new(index_key());
+        let token = cursor.encode_signed(b"secret");
+        assert_eq!(
+            Cursor::decode_signed(&token, &timeline(), b"secret").unwrap(),
+            cursor
+        );
+
+        let other_secret = Cursor::decode_signed(&token, &timeline(), b"other");
+        let (_, signature) = token.split_once(SIGNATURE_SEPARATOR).unwrap();
+        let other_payload = Cursor::new(key(&[("pk", s("u#9")), ("sk", s("p#2"))])).encode();
+        let swapped = Cursor::decode_signed(
+            &format!("{other_payload}{SIGNATURE_SEPARATOR}{signature}"),
+            &timeline(),
+            b"secret",
+        );
+        let unsigned = Cursor::decode_signed(&cursor.encode(), &timeline(), b"secret");
+        for result in [other_secret, swapped, unsigned] {
+            assert!(matches!(result, Err(CursorError::Signature)));
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{CursorError, ExpectedVersion, Item, ItemError};
+use aws_sdk_dynamodb::error::SdkError;
+use std::fmt::Display;
+
+/// Everything a request made through an entity can fail with.
+#[derive(Debug)]
+pub enum Error {
+    /// DynamoDB rejected the request or could not be reached.
+    Sdk(Box<aws_sdk_dynamodb::Error>),
+    /// An item came back that does not match the entity's schema.
+    Item(ItemError),
+    /// The request does not fit the entity's schema, such as a query on an
+    /// index the entity does not declare.
+    InvalidRequest(String),
+    /// A page token could not be read back.
+    Cursor(CursorError),
+    /// A conditional write found the item missing or not matching its
+    /// condition, so nothing was written.
+    ConditionFailed,
+    /// The item was written by someone else since it was read. `found` is the
+    /// stored version, `None` if the stored item has none.
+    VersionConflict { expected: u64, found: Option<u64> },
+    /// An operation of a transaction failed, so none of them were applied.
+    /// `operation` is its position in the transaction.
+    TransactionCanceled {
+        operation: usize,
+        reason: Box<Error>,
+    },
+}
+
+/// The cancellation reason of a transaction action whose condition failed.
+const CONDITIONAL_CHECK_FAILED: &str = "ConditionalCheckFailed";
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Sdk(err) => write!(f, "{err}"),
+            Error::Item(err) => write!(f, "{err}"),
+            Error::InvalidRequest(message) => write!(f, "invalid request: {message}"),
+            Error::Cursor(err) => write!(f, "{err}"),
+            Error::ConditionFailed => write!(f, "the condition of the write was not met"),
+            Error::VersionConflict { expected, found } => match found {
+                Some(found) => write!(f, "expected version {expected}, found version {found}"),
+                None => write!(f, "expected version {expected}, found an unversioned item"),
+            },
+            Error::TransactionCanceled { operation, reason } => {
+                write!(
+                    f,
+                    "operation {operation} of the transaction failed: {reason}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Sdk(err) => Some(err.as_ref()),
+            Error::Item(err) => Some(err),
+            Error::InvalidRequest(_) | Error::ConditionFailed | Error::VersionConflict { .. } => {
+                None
+            }
+            Error::Cursor(err) => Some(err),
+            Error::TransactionCanceled { reason, .. } => Some(reason.as_ref()),
+        }
+    }
+}
+
+impl From<ItemError> for Error {
+    fn from(err: ItemError) -> Self {
+        Error::Item(err)
+    }
+}
+
+impl From<CursorError> for Error {
+    fn from(err: CursorError) -> Self {
+        Error::Cursor(err)
+    }
+}
+
+impl<E, R> From<SdkError<E, R>> for Error
+where
+    aws_sdk_dynamodb::Error: From<SdkError<E, R>>,
+{
+    fn from(err: SdkError<E, R>) -> Self {
+        Error::from(aws_sdk_dynamodb::Error::from(err))
+    }
+}
+
+impl From<aws_sdk_dynamodb::Error> for Error {
+    fn from(err: aws_sdk_dynamodb::Error) -> Self {
+        match err {
+            aws_sdk_dynamodb::Error::ConditionalCheckFailedException(_) => Error::ConditionFailed,
+            // A transaction reports each action's failure as a cancellation reason
+            aws_sdk_dynamodb::Error::TransactionCanceledException(err)
+                if err
+                    .cancellation_reasons()
+                    .iter()
+                    .any(|reason| reason.code() == Some(CONDITIONAL_CHECK_FAILED)) =>
+            {
+                Error::ConditionFailed
+            }
+            err => Error::Sdk(Box::new(err)),
+        }
+    }
+}
+
+impl Error {
+    /// Like the `From` conversion, but a failed write that expected `version`
+    /// and finds the stored item at another one is a [`Error::VersionConflict`].
+    /// The stored item comes back with the error when the write asks for
+    /// `ReturnValuesOnConditionCheckFailure::AllOld`.
+    pub(crate) fn from_versioned<E, R>(
+        err: SdkError<E, R>,
+        version: Option<&ExpectedVersion>,
+    ) -> Self
+    where
+        aws_sdk_dynamodb::Error: From<SdkError<E, R>>,
+    {
+        let err = aws_sdk_dynamodb::Error::from(err);
+        let Some(expected) = version else {
+            return Error::from(err);
+        };
+        let old_items = match &err {
+            aws_sdk_dynamodb::Error::ConditionalCheckFailedException(err) => {
+                err.item().into_iter().collect()
+            }
+            aws_sdk_dynamodb::Error::TransactionCanceledException(err) => err
+                .cancellation_reasons()
+                .iter()
+                .filter_map(|reason| reason.item())
+                .collect(),
+            _ => vec![],
+        };
+        old_items
+            .into_iter()
+            .find_map(|item| version_conflict(item, expected))
+            .unwrap_or_else(|| Error::from(err))
+    }
+
+    /// Like the `From` conversion, but names the first operation of a
+    /// transaction whose condition failed. `versions` holds what each
+    /// operation expected, in order.
+    pub(crate) fn from_transaction<E, R>(
+        err: SdkError<E, R>,
+        versions: &[Option<ExpectedVersion>],
+    ) -> Self
+    where
+        aws_sdk_dynamodb::Error: From<SdkError<E, R>>,
+    {
+        let err = aws_sdk_dynamodb::Error::from(err);
+        // Reasons line up with the operations, with `None` for those that passed
+        if let aws_sdk_dynamodb::Error::TransactionCanceledException(canceled) = &err
+            && let Some((operation, reason)) = canceled
+                .cancellation_reasons()
+                .iter()
+                .enumerate()
+                .find(|(_, reason)| reason.code() == Some(CONDITIONAL_CHECK_FAILED))
+        {
+            let reason = versions
+                .get(operation)
+                .and_then(Option::as_ref)
+                .zip(reason.item())
+                .and_then(|(expected, item)| version_conflict(item, expected))
+                .unwrap_or(Error::ConditionFailed);
+            return Error::TransactionCanceled {
+                operation,
+                reason: Box::new(reason),
+            };
+        }
+        Error::from(err)
+    }
+}
+
+/// A [`Error::VersionConflict`] if the stored `item` is not at the expected version.
+fn version_conflict(item: &Item, expected: &ExpectedVersion) -> Option<Error> {
+    let found = item
+        .get(&expected.attribute)
+        .and_then(|value| value.as_n().ok())
+        .and_then(|n| n.parse().ok());
+    (found != Some(expected.version)).then_some(Error::VersionConflict {
+        expected: expected.version,
+        found,
+    })
+}
@@ -0,0 +1,245 @@
+use crate::{FormattedSegment, Item, SchemaV2};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+//
+// ─── ITEM DECODING ──────────────────────────────────────────────────────────────
+//
+#[derive(Debug)]
+pub enum ItemError {
+    MissingAttribute(String),
+    MalformedAttribute { attribute: String, value: String },
+    InvalidField { field: String, message: String },
+}
+
+impl Display for ItemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ItemError::MissingAttribute(attribute) => {
+                write!(f, "item has no attribute `{attribute}`")
+            }
+            ItemError::MalformedAttribute { attribute, value } => {
+                write!(
+                    f,
+                    "attribute `{attribute}` does not match its schema: {value:?}"
+                )
+            }
+            ItemError::InvalidField { field, message } => {
+                write!(f, "cannot decode field `{field}`: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ItemError {}
+
+impl SchemaV2 {
+    /// Decodes every composite attribute in `item`, returning the raw segment
+    /// value of each struct field that feeds a key.
+    pub fn decode_segments(
+        &self,
+        item: &serde_json::Value,
+    ) -> Result<HashMap<String, String>, ItemError> {
+        let mut values = HashMap::new();
+        for (attribute_name, composite) in self.composite_defs() {
+            let value = match item.get(attribute_name) {
+                Some(serde_json::Value::String(s)) => s.clone(),
+                Some(serde_json::Value::Number(n)) => n.to_string(),
+                Some(serde_json::Value::Bool(b)) => b.to_string(),
+                None | Some(serde_json::Value::Null) if composite.is_sparse() => continue,
+                _ => return Err(ItemError::MissingAttribute(attribute_name.to_string())),
+            };
+            let fields = composite.parse(&value, self.delimiter).ok_or_else(|| {
+                ItemError::MalformedAttribute {
+                    attribute: attribute_name.to_string(),
+                    value: value.clone(),
+                }
+            })?;
+            for (field, raw) in fields {
+                values.entry(field).or_insert(raw);
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// Parses a field that was decoded from a composite key.
+pub fn segment_value<T>(values: &HashMap<String, String>, field: &str) -> Result<T, ItemError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let raw = values
+        .get(field)
+        .ok_or_else(|| ItemError::MissingAttribute(field.to_string()))?;
+    raw.parse().map_err(|err: T::Err| ItemError::InvalidField {
+        field: field.to_string(),
+        message: err.to_string(),
+    })
+}
+
+/// Parses a field that was decoded from a composite key with a `format`.
+pub fn formatted_segment_value<T: FormattedSegment>(
+    values: &HashMap<String, String>,
+    field: &str,
+    format: &str,
+) -> Result<T, ItemError> {
+    let raw = values
+        .get(field)
+        .ok_or_else(|| ItemError::MissingAttribute(field.to_string()))?;
+    T::parse_segment(raw, format).map_err(|message| ItemError::InvalidField {
+        field: field.to_string(),
+        message,
+    })
+}
+
+/// Reads an `Option` field from a key segment. A field missing from a sparse
+/// attribute that was left out of the item is `None`.
+pub fn optional_segment<T>(value: Result<T, ItemError>) -> Result<Option<T>, ItemError> {
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(ItemError::MissingAttribute(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Deserializes a field that is stored as a plain attribute of the same name.
+pub fn field_value<T: DeserializeOwned>(
+    item: &serde_json::Value,
+    field: &str,
+) -> Result<T, ItemError> {
+    let value = item.get(field).cloned().unwrap_or(serde_json::Value::Null);
+    serde_json::from_value(value).map_err(|err| match item.get(field) {
+        None => ItemError::MissingAttribute(field.to_string()),
+        Some(_) => ItemError::InvalidField {
+            field: field.to_string(),
+            message: err.to_string(),
+        },
+    })
+}
+
+/// Deserializes a field from the typed attribute it would be stored as.
+pub fn attribute_field_value<T: DeserializeOwned>(
+    value: &aws_sdk_dynamodb::types::AttributeValue,
+    field: &str,
+) -> Result<T, ItemError> {
+    serde_dynamo::from_attribute_value(value.clone()).map_err(|err| ItemError::InvalidField {
+        field: field.to_string(),
+        message: err.to_string(),
+    })
+}
+
+/// Converts an SDK item into the JSON shape accepted by [`crate::Entity2::from_item`].
+pub fn attribute_map_to_json(item: &Item) -> serde_json::Value {
+    serde_json::Value::Object(
+        item.iter()
+            .map(|(name, value)| (name.clone(), attribute_value_to_json(value)))
+            .collect(),
+    )
+}
+
+fn attribute_value_to_json(value: &aws_sdk_dynamodb::types::AttributeValue) -> serde_json::Value {
+    use aws_sdk_dynamodb::types::AttributeValue as Av;
+    use serde_json::Value;
+
+    let number = |n: &str| {
+        serde_json::from_str::<serde_json::Number>(n)
+            .map(Value::Number)
+            .unwrap_or_else(|_| Value::String(n.to_string()))
+    };
+    let bytes = |b: &aws_sdk_dynamodb::primitives::Blob| {
+        Value::Array(b.as_ref().iter().map(|byte| Value::from(*byte)).collect())
+    };
+
+    match value {
+        Av::S(s) => Value::String(s.clone()),
+        Av::N(n) => number(n),
+        Av::Bool(b) => Value::Bool(*b),
+        Av::Null(_) => Value::Null,
+        Av::B(b) => bytes(b),
+        Av::Ss(ss) => Value::Array(ss.iter().cloned().map(Value::String).collect()),
+        Av::Ns(ns) => Value::Array(ns.iter().map(|n| number(n)).collect()),
+        Av::Bs(bs) => Value::Array(bs.iter().map(bytes).collect()),
+        Av::L(l) => Value::Array(l.iter().map(attribute_value_to_json).collect()),
+        Av::M(m) => attribute_map_to_json(m),
+        _ => Value::Null,
+    }
+}
+
+//
+// ─── ITEM ENCODING ──────────────────────────────────────────────────────────────
+//
+
+/// Serializes every struct field through `serde_dynamo`, keeping numbers,
+/// booleans and nested values in their native DynamoDB types.
+pub fn serialize_attributes<T: Serialize>(entity: &T) -> Item {
+    serde_dynamo::to_item(entity).expect("Dynodmize entities serialize to a map")
+}
+
+/// Serializes one field through `serde_dynamo`, as [`serialize_attributes`] does
+/// for a whole struct.
+pub fn serialize_attribute<T: Serialize>(value: &T) -> aws_sdk_dynamodb::types::AttributeValue {
+    serde_dynamo::to_attribute_value(value).expect("Dynodmize fields serialize to an attribute")
+}
+
+/// Writes a field into a key segment with its `format`.
+pub fn formatted_segment<T: FormattedSegment>(
+    value: &T,
+    field: &str,
+    format: &str,
+) -> Result<String, ItemError> {
+    value
+        .format_segment(format)
+        .map_err(|message| ItemError::InvalidField {
+            field: field.to_string(),
+            message,
+        })
+}
+
+/// Stores a byte buffer as a binary (`B`) attribute rather than a list of numbers.
+pub fn binary_attribute(bytes: &[u8]) -> aws_sdk_dynamodb::types::AttributeValue {
+    aws_sdk_dynamodb::types::AttributeValue::B(aws_sdk_dynamodb::primitives::Blob::new(bytes))
+}
+
+impl SchemaV2 {
+    /// Adds the derived key attributes to `item`, which holds the typed field
+    /// attributes. `values` holds the string form of the fields that feed keys.
+    ///
+    /// A key made of a single bare segment keeps the field's own type, so a
+    /// numeric `#[nk]` stays an `N`. Every other composite and static key is an
+    /// `S`. Sparse attributes whose fields are `None` are removed.
+    pub fn merge_key_attributes(&self, item: &mut Item, values: &HashMap<String, String>) {
+        use aws_sdk_dynamodb::types::AttributeValue as Av;
+
+        for attribute_name in self.sparse_attributes(values) {
+            item.remove(attribute_name);
+        }
+
+        let bare_fields: HashMap<&str, &str> = self
+            .composite_defs()
+            .filter_map(|(name, composite)| Some((name, composite.bare_field()?)))
+            .collect();
+
+        for (attribute_name, value) in self.render_attributes(values) {
+            let typed = bare_fields
+                .get(attribute_name.as_str())
+                .and_then(|field| item.get(*field))
+                .cloned();
+            item.insert(attribute_name, typed.unwrap_or(Av::S(value)));
+        }
+    }
+
+    /// The table's pk and sk attributes alone, typed the same way as in
+    /// [`SchemaV2::merge_key_attributes`]. `fields` needs the typed attributes
+    /// of at least the fields that feed those keys.
+    pub fn key_attributes(&self, fields: &Item, values: &HashMap<String, String>) -> Item {
+        let mut item = fields.clone();
+        self.merge_key_attributes(&mut item, values);
+        self.key_attribute_names()
+            .filter_map(|name| Some((name.to_string(), item.remove(name)?)))
+            .collect()
+    }
+}
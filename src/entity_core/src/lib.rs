@@ -1,8 +1,44 @@
-use aws_sdk_dynamodb::{Client, Error};
+mod batch;
+mod condition;
+mod cursor;
+mod error;
+mod item;
+mod page;
+mod schema;
+mod segment_format;
+mod table;
+mod transaction;
+
+pub use batch::*;
+pub use condition::*;
+pub use cursor::*;
+pub use error::*;
+pub use item::*;
+pub use page::*;
+pub use schema::*;
+pub use segment_format::*;
+pub use table::*;
+pub use transaction::*;
+
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue as Av;
+use futures::{Stream, StreamExt, TryStreamExt};
 use serde::Serialize;
-use serde_dynamo::to_item;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
 use std::fmt::Debug;
 
+/// The crates generated code names, so that crates deriving entities need not
+/// depend on them at matching versions. Not part of the public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use aws_sdk_dynamodb;
+    pub use serde_json;
+}
+
+/// An item in the shape the DynamoDB SDK sends and receives.
+pub type Item = HashMap<String, aws_sdk_dynamodb::types::AttributeValue>;
+
 //
 // ─── ENTITY TRAIT ───────────────────────────────────────────────────────────────
 //
@@ -12,9 +48,155 @@ pub trait Dynodmize {
         None
     }
 }
-pub trait Entity2 {
+pub trait Entity2: Sized {
     fn get_schema() -> SchemaV2;
-    fn to_item(&self) -> serde_json::Value;
+    /// The schema this value is written with. Only enums override it, as each
+    /// variant has its own key layout.
+    fn schema(&self) -> SchemaV2 {
+        Self::get_schema()
+    }
+    /// The schema of each variant of an enum, which `get_schema` only holds
+    /// what is shared of. Structs have none.
+    fn variant_schemas() -> Vec<SchemaV2> {
+        vec![]
+    }
+    /// The string form of every field that feeds a derived attribute, keyed by
+    /// struct field name. This is what the schema's key renderer consumes. Fails
+    /// if a field's `format` cannot write it.
+    fn segment_values(&self) -> Result<HashMap<String, String>, ItemError>;
+    /// The string form of one field that feeds a derived attribute, given the
+    /// field as a typed attribute. Updates use it to recompute derived
+    /// attributes. Enums do not implement it, as the field's type depends on
+    /// the variant.
+    fn segment_value(field: &str, _value: &Av) -> Result<String, ItemError> {
+        Err(ItemError::InvalidField {
+            field: field.to_string(),
+            message: "cannot be rendered on its own".to_string(),
+        })
+    }
+    /// Every field as a typed attribute, before the derived attributes are added.
+    fn field_attributes(&self) -> Item;
+    fn from_item(item: &serde_json::Value) -> Result<Self, ItemError>;
+
+    /// Serialize to `serde_json::Value`, with the same attributes and types as
+    /// `to_attribute_map`: derived attributes are strings, except a bare key,
+    /// which keeps its field's type
+    fn to_item(&self) -> Result<serde_json::Value, ItemError> {
+        Ok(attribute_map_to_json(&self.to_attribute_map()?))
+    }
+
+    /// Serialize to DynamoDB attribute values, keeping each field's native type
+    fn to_attribute_map(&self) -> Result<Item, ItemError> {
+        let mut item = self.field_attributes();
+        self.schema()
+            .merge_key_attributes(&mut item, &self.segment_values()?);
+        Ok(item)
+    }
+
+    fn from_attribute_map(item: &Item) -> Result<Self, ItemError> {
+        Self::from_item(&attribute_map_to_json(item))
+    }
+
+    /// This value's table pk and sk attributes alone.
+    fn key_attributes(&self) -> Result<Item, ItemError> {
+        Ok(self
+            .schema()
+            .key_attributes(&self.field_attributes(), &self.segment_values()?))
+    }
+}
+
+/// The table keys of an entity without the rest of its fields. The derive
+/// generates one per struct, e.g. `UserFollowerKey` for `UserFollower`.
+pub trait PrimaryKey {
+    type Entity: Entity2;
+
+    /// The string form of each key field, keyed by field name.
+    fn segment_values(&self) -> Result<HashMap<String, String>, ItemError>;
+    /// Each key field as a typed attribute.
+    fn field_attributes(&self) -> Item;
+
+    /// The pk and sk attributes, as `GetItem` and `DeleteItem` expect them.
+    fn to_key(&self) -> Result<Item, ItemError> {
+        Ok(Self::Entity::get_schema()
+            .key_attributes(&self.field_attributes(), &self.segment_values()?))
+    }
+}
+
+//
+// ─── WRITES ─────────────────────────────────────────────────────────────────────
+//
+/// The version a write expects to find in the stored item, so that a write
+/// made since the entity was read is caught.
+#[derive(Debug, Clone)]
+pub(crate) struct ExpectedVersion {
+    pub(crate) attribute: String,
+    pub(crate) version: u64,
+}
+
+impl ExpectedVersion {
+    fn condition<T>(&self) -> Condition<T> {
+        Condition::version_is(&self.attribute, self.version)
+    }
+
+    /// The version the write stores.
+    fn next(&self) -> Av {
+        Av::N((self.version + 1).to_string())
+    }
+}
+
+/// A put, update or delete, ready to be sent on its own or in a [`Transaction`].
+pub(crate) struct Write {
+    /// The item to put, or the key of the item to update or delete
+    pub(crate) item: Item,
+    pub(crate) update_expression: Option<String>,
+    pub(crate) condition: Option<String>,
+    pub(crate) names: HashMap<String, String>,
+    pub(crate) values: Item,
+    pub(crate) version: Option<ExpectedVersion>,
+}
+
+impl Write {
+    fn new(item: Item) -> Self {
+        Write {
+            item,
+            update_expression: None,
+            condition: None,
+            names: HashMap::new(),
+            values: Item::new(),
+            version: None,
+        }
+    }
+
+    /// Renders `condition` after the placeholders the write already uses.
+    fn with_condition<T>(mut self, condition: Option<&Condition<T>>, schema: &SchemaV2) -> Self {
+        self.condition = condition
+            .map(|condition| condition.expression(schema, &mut self.names, &mut self.values));
+        self
+    }
+
+    /// A versioned write gets the stored item back when its condition fails,
+    /// to tell a version conflict apart from other failed conditions.
+    pub(crate) fn return_old(
+        &self,
+    ) -> Option<aws_sdk_dynamodb::types::ReturnValuesOnConditionCheckFailure> {
+        self.version.as_ref().map(|_| ALL_OLD)
+    }
+}
+
+const ALL_OLD: aws_sdk_dynamodb::types::ReturnValuesOnConditionCheckFailure =
+    aws_sdk_dynamodb::types::ReturnValuesOnConditionCheckFailure::AllOld;
+
+/// `None` for an empty map, which DynamoDB rejects.
+pub(crate) fn non_empty<V>(map: HashMap<String, V>) -> Option<HashMap<String, V>> {
+    (!map.is_empty()).then_some(map)
+}
+
+/// The version an entity was read at, from its `#[version]` attribute.
+fn stored_version(item: &Item, attribute: &str) -> Result<u64, Error> {
+    let value = item
+        .get(attribute)
+        .ok_or_else(|| ItemError::MissingAttribute(attribute.to_string()))?;
+    Ok(attribute_field_value(value, attribute)?)
 }
 
 //
@@ -23,19 +205,177 @@ pub trait Entity2 {
 pub struct CreateBuilder<T> {
     pub entity: T,
     pub client: Client,
+    pub condition: Option<Condition<T>>,
+    pub tables: TableConfig,
 }
 
-impl<T: Debug + Serialize> CreateBuilder<T> {
+impl<T: Debug> CreateBuilder<T> {
     pub fn send(self) {
         println!("Creating entity: {:?}", self.entity);
     }
+}
+
+impl<T: Entity2> CreateBuilder<T> {
+    pub fn new(entity: T, client: Client) -> Self {
+        CreateBuilder {
+            entity,
+            client,
+            condition: None,
+            tables: TableConfig::default(),
+        }
+    }
+
+    /// Resolves the table through `tables` instead of the entity's own.
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.tables = tables;
+        self
+    }
+
+    /// Only writes if `condition` holds for the item currently stored under
+    /// the key, failing with [`Error::ConditionFailed`] otherwise.
+    pub fn condition(mut self, condition: Condition<T>) -> Self {
+        self.condition = Some(condition);
+        self
+    }
 
+    /// Only writes if nothing is stored under the key yet.
+    pub fn if_not_exists(self) -> Self {
+        self.condition(Condition::item_not_exists())
+    }
+
+    /// The put, at the next version for a versioned entity. It then only goes
+    /// through if the stored item is still at the entity's version or there is none.
+    pub(crate) fn prepare(self) -> Result<Write, Error> {
+        let schema = self.entity.schema();
+        let mut write = Write::new(self.entity.to_attribute_map()?);
+        let mut condition = self.condition;
+        if let Some(attribute) = &schema.version_attribute {
+            let expected = ExpectedVersion {
+                attribute: attribute.clone(),
+                version: stored_version(&write.item, attribute)?,
+            };
+            write.item.insert(attribute.clone(), expected.next());
+            let guard = Condition::item_not_exists().or(expected.condition());
+            condition = Some(match condition {
+                Some(condition) => guard.and(condition),
+                None => guard,
+            });
+            write.version = Some(expected);
+        }
+        Ok(write.with_condition(condition.as_ref(), &schema))
+    }
+
+    /// Puts the entity. A versioned entity is stored at the next version, and
+    /// only if the stored item is still at the entity's version or there is none.
     pub async fn send2(self) -> Result<(), Error> {
-        let item = Some(to_item(self.entity).unwrap());
-        self.client
+        let client = self.client.clone();
+        let table = self.tables.resolve::<T>()?;
+        let write = self.prepare()?;
+        let return_old = write.return_old();
+        client
             .put_item()
-            .table_name("test")
-            .set_item(item)
+            .table_name(table)
+            .set_item(Some(write.item))
+            .set_condition_expression(write.condition)
+            .set_expression_attribute_names(non_empty(write.names))
+            .set_expression_attribute_values(non_empty(write.values))
+            .set_return_values_on_condition_check_failure(return_old)
+            .send()
+            .await
+            .map_err(|err| Error::from_versioned(err, write.version.as_ref()))?;
+        Ok(())
+    }
+}
+
+//
+// ─── GET BUILDER ────────────────────────────────────────────────────────────────
+//
+pub struct GetBuilder<K> {
+    pub key: K,
+    pub client: Client,
+    pub tables: TableConfig,
+}
+
+impl<K: PrimaryKey> GetBuilder<K> {
+    pub fn new(key: K, client: Client) -> Self {
+        GetBuilder {
+            key,
+            client,
+            tables: TableConfig::default(),
+        }
+    }
+
+    /// Resolves the table through `tables` instead of the entity's own.
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.tables = tables;
+        self
+    }
+
+    /// Fetches the item, or `None` if there is none under this key.
+    pub async fn send(self) -> Result<Option<K::Entity>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(self.tables.resolve::<K::Entity>()?)
+            .set_key(Some(self.key.to_key()?))
+            .send()
+            .await?;
+        match output.item {
+            Some(item) => Ok(Some(K::Entity::from_attribute_map(&item)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+//
+// ─── DELETE BUILDER ─────────────────────────────────────────────────────────────
+//
+pub struct DeleteBuilder<K: PrimaryKey> {
+    pub key: K,
+    pub client: Client,
+    pub condition: Option<Condition<K::Entity>>,
+    pub tables: TableConfig,
+}
+
+impl<K: PrimaryKey> DeleteBuilder<K> {
+    pub fn new(key: K, client: Client) -> Self {
+        DeleteBuilder {
+            key,
+            client,
+            condition: None,
+            tables: TableConfig::default(),
+        }
+    }
+
+    /// Resolves the table through `tables` instead of the entity's own.
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.tables = tables;
+        self
+    }
+
+    /// Only deletes if `condition` holds for the stored item, failing with
+    /// [`Error::ConditionFailed`] otherwise.
+    pub fn condition(mut self, condition: Condition<K::Entity>) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    pub(crate) fn prepare(self) -> Result<Write, Error> {
+        Ok(Write::new(self.key.to_key()?)
+            .with_condition(self.condition.as_ref(), &K::Entity::get_schema()))
+    }
+
+    pub async fn send(self) -> Result<(), Error> {
+        let client = self.client.clone();
+        let table = self.tables.resolve::<K::Entity>()?;
+        let write = self.prepare()?;
+        client
+            .delete_item()
+            .table_name(table)
+            .set_key(Some(write.item))
+            .set_condition_expression(write.condition)
+            .set_expression_attribute_names(non_empty(write.names))
+            .set_expression_attribute_values(non_empty(write.values))
             .send()
             .await?;
         Ok(())
@@ -45,48 +385,1012 @@ impl<T: Debug + Serialize> CreateBuilder<T> {
 //
 // ─── QUERY BUILDER ──────────────────────────────────────────────────────────────
 //
+/// A condition on the sort key of a query. Values are serialized the same way
+/// as fields, so a numeric sort key takes a number.
+#[derive(Debug, Clone)]
+pub enum SortKeyCondition {
+    Eq(Av),
+    Lt(Av),
+    Le(Av),
+    Gt(Av),
+    Ge(Av),
+    Between(Av, Av),
+    BeginsWith(String),
+}
+
+impl SortKeyCondition {
+    pub fn eq<V: Serialize>(value: V) -> Self {
+        Self::Eq(serialize_attribute(&value))
+    }
+
+    pub fn lt<V: Serialize>(value: V) -> Self {
+        Self::Lt(serialize_attribute(&value))
+    }
+
+    pub fn le<V: Serialize>(value: V) -> Self {
+        Self::Le(serialize_attribute(&value))
+    }
+
+    pub fn gt<V: Serialize>(value: V) -> Self {
+        Self::Gt(serialize_attribute(&value))
+    }
+
+    pub fn ge<V: Serialize>(value: V) -> Self {
+        Self::Ge(serialize_attribute(&value))
+    }
+
+    /// Both ends are inclusive.
+    pub fn between<V: Serialize>(low: V, high: V) -> Self {
+        Self::Between(serialize_attribute(&low), serialize_attribute(&high))
+    }
+
+    /// Takes a rendered prefix, such as one from a generated `sk_prefix()`.
+    pub fn begins_with(prefix: impl std::fmt::Display) -> Self {
+        Self::BeginsWith(prefix.to_string())
+    }
+
+    /// The condition on `#sk`, with its operands added to `values`.
+    fn expression(&self, values: &mut Item) -> String {
+        let mut bind = |placeholder: &str, value: Av| {
+            values.insert(placeholder.to_string(), value);
+            placeholder.to_string()
+        };
+        match self.clone() {
+            Self::Eq(value) => format!("#sk = {}", bind(":sk", value)),
+            Self::Lt(value) => format!("#sk < {}", bind(":sk", value)),
+            Self::Le(value) => format!("#sk <= {}", bind(":sk", value)),
+            Self::Gt(value) => format!("#sk > {}", bind(":sk", value)),
+            Self::Ge(value) => format!("#sk >= {}", bind(":sk", value)),
+            Self::Between(low, high) => format!(
+                "#sk BETWEEN {} AND {}",
+                bind(":sk_low", low),
+                bind(":sk_high", high)
+            ),
+            Self::BeginsWith(prefix) => {
+                format!("begins_with(#sk, {})", bind(":sk", Av::S(prefix)))
+            }
+        }
+    }
+}
+
 pub struct QueryBuilder<T> {
-    pub partition_key: Option<String>,
+    pub client: Client,
+    pub index_name: Option<String>,
+    pub partition_key: Option<Av>,
+    pub sort_key: Option<SortKeyCondition>,
+    pub scan_index_forward: Option<bool>,
+    pub limit: Option<i32>,
+    pub exclusive_start_key: Option<Item>,
+    pub tables: TableConfig,
+    pub _marker: std::marker::PhantomData<T>,
+}
+
+impl<T: Entity2> QueryBuilder<T> {
+    pub fn new(client: Client) -> Self {
+        QueryBuilder {
+            client,
+            index_name: None,
+            partition_key: None,
+            sort_key: None,
+            scan_index_forward: None,
+            limit: None,
+            exclusive_start_key: None,
+            tables: TableConfig::default(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Resolves the table through `tables` instead of the entity's own.
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.tables = tables;
+        self
+    }
+
+    /// The rendered partition key, e.g. `"u#123"`, or the field value of a
+    /// bare numeric key.
+    pub fn where_partition_key<V: Serialize>(mut self, key: V) -> Self {
+        self.partition_key = Some(serialize_attribute(&key));
+        self
+    }
+
+    pub fn where_sort_key(mut self, condition: SortKeyCondition) -> Self {
+        self.sort_key = Some(condition);
+        self
+    }
+
+    /// Queries a secondary index declared on the entity instead of the table.
+    pub fn using_index(mut self, index_name: &str) -> Self {
+        self.index_name = Some(index_name.to_owned());
+        self
+    }
+
+    /// Returns items in descending sort key order.
+    pub fn descending(mut self) -> Self {
+        self.scan_index_forward = Some(false);
+        self
+    }
+
+    /// The most items DynamoDB evaluates per page.
+    pub fn limit(mut self, limit: i32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Resumes from the `last_evaluated_key` or [`Cursor`] of an earlier page.
+    pub fn start_from(mut self, key: impl Into<Item>) -> Self {
+        self.exclusive_start_key = Some(key.into());
+        self
+    }
+
+    /// The key condition and its placeholders, checked against the schema.
+    fn request(
+        &self,
+    ) -> Result<aws_sdk_dynamodb::operation::query::builders::QueryFluentBuilder, Error> {
+        let schema = T::get_schema();
+        let (pk_name, sk_name) = match &self.index_name {
+            None => (
+                schema.partition_key_def.attribute_name.clone(),
+                schema
+                    .sort_key_def
+                    .as_ref()
+                    .map(|sk| sk.attribute_name.clone()),
+            ),
+            Some(index_name) => {
+                let index = schema.index(index_name).ok_or_else(|| {
+                    Error::InvalidRequest(format!("the entity has no index `{index_name}`"))
+                })?;
+                (
+                    index.partition_key_def.attribute_name.clone(),
+                    index
+                        .sort_key_def
+                        .as_ref()
+                        .map(|sk| sk.attribute_name.clone()),
+                )
+            }
+        };
+
+        let partition_key = self
+            .partition_key
+            .clone()
+            .ok_or_else(|| Error::InvalidRequest("a query needs a partition key".to_string()))?;
+        let mut names = HashMap::from([("#pk".to_string(), pk_name)]);
+        let mut values = Item::from([(":pk".to_string(), partition_key)]);
+        let mut key_condition = "#pk = :pk".to_string();
+
+        if let Some(condition) = &self.sort_key {
+            let sk_name = sk_name.ok_or_else(|| {
+                Error::InvalidRequest("a sort key condition needs a sort key".to_string())
+            })?;
+            names.insert("#sk".to_string(), sk_name);
+            key_condition = format!("{key_condition} AND {}", condition.expression(&mut values));
+        }
+
+        Ok(self
+            .client
+            .query()
+            .table_name(self.tables.resolve::<T>()?)
+            .set_index_name(self.index_name.clone())
+            .key_condition_expression(key_condition)
+            .set_expression_attribute_names(Some(names))
+            .set_expression_attribute_values(Some(values))
+            .set_scan_index_forward(self.scan_index_forward)
+            .set_limit(self.limit))
+    }
+
+    /// Fetches a single page.
+    pub async fn send_page(self) -> Result<Page<T>, Error> {
+        let output = self
+            .request()?
+            .set_exclusive_start_key(self.exclusive_start_key)
+            .send()
+            .await?;
+        Page::decode(output.items, output.last_evaluated_key)
+    }
+
+    /// Every page from the start key on, each requested once the previous one
+    /// has been consumed.
+    pub fn into_pages(self) -> impl Stream<Item = Result<Page<T>, Error>> {
+        match self.request() {
+            Ok(request) => pages(self.exclusive_start_key, move |start_key| {
+                let request = request.clone().set_exclusive_start_key(start_key);
+                async move {
+                    let output = request.send().await?;
+                    Page::decode(output.items, output.last_evaluated_key)
+                }
+            })
+            .left_stream(),
+            Err(err) => failed(err).right_stream(),
+        }
+    }
+
+    /// Every matching item, fetched page by page as the stream is polled.
+    pub fn into_stream(self) -> impl Stream<Item = Result<T, Error>> {
+        page_items(self.into_pages())
+    }
+
+    /// Runs the query, following every page, and decodes each item.
+    pub async fn send(self) -> Result<Vec<T>, Error> {
+        self.into_stream().try_collect().await
+    }
+}
+
+//
+// ─── SCAN BUILDER ───────────────────────────────────────────────────────────────
+//
+/// Reads the whole table or index. Every item must decode into `T`, so this
+/// is meant for tables or indexes holding a single entity.
+pub struct ScanBuilder<T> {
+    pub client: Client,
+    pub index_name: Option<String>,
+    pub limit: Option<i32>,
+    pub exclusive_start_key: Option<Item>,
+    pub tables: TableConfig,
     pub _marker: std::marker::PhantomData<T>,
 }
 
-impl<T: Debug + Default> QueryBuilder<T> {
-    pub fn where_partition_key(mut self, key: &str) -> Self {
-        self.partition_key = Some(key.to_owned());
+impl<T: Entity2> ScanBuilder<T> {
+    pub fn new(client: Client) -> Self {
+        ScanBuilder {
+            client,
+            index_name: None,
+            limit: None,
+            exclusive_start_key: None,
+            tables: TableConfig::default(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Resolves the table through `tables` instead of the entity's own.
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.tables = tables;
+        self
+    }
+
+    /// Scans a secondary index declared on the entity instead of the table.
+    pub fn using_index(mut self, index_name: &str) -> Self {
+        self.index_name = Some(index_name.to_owned());
+        self
+    }
+
+    /// The most items DynamoDB evaluates per page.
+    pub fn limit(mut self, limit: i32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Resumes from the `last_evaluated_key` or [`Cursor`] of an earlier page.
+    pub fn start_from(mut self, key: impl Into<Item>) -> Self {
+        self.exclusive_start_key = Some(key.into());
         self
     }
 
-    pub fn send(self) -> Vec<T> {
-        println!("Query on pk={:?}", self.partition_key);
-        vec![T::default()]
+    fn request(
+        &self,
+    ) -> Result<aws_sdk_dynamodb::operation::scan::builders::ScanFluentBuilder, Error> {
+        if let Some(index_name) = &self.index_name
+            && T::get_schema().index(index_name).is_none()
+        {
+            return Err(Error::InvalidRequest(format!(
+                "the entity has no index `{index_name}`"
+            )));
+        }
+        Ok(self
+            .client
+            .scan()
+            .table_name(self.tables.resolve::<T>()?)
+            .set_index_name(self.index_name.clone())
+            .set_limit(self.limit))
+    }
+
+    /// Fetches a single page.
+    pub async fn send_page(self) -> Result<Page<T>, Error> {
+        let output = self
+            .request()?
+            .set_exclusive_start_key(self.exclusive_start_key)
+            .send()
+            .await?;
+        Page::decode(output.items, output.last_evaluated_key)
+    }
+
+    /// Every page from the start key on, each requested once the previous one
+    /// has been consumed.
+    pub fn into_pages(self) -> impl Stream<Item = Result<Page<T>, Error>> {
+        match self.request() {
+            Ok(request) => pages(self.exclusive_start_key, move |start_key| {
+                let request = request.clone().set_exclusive_start_key(start_key);
+                async move {
+                    let output = request.send().await?;
+                    Page::decode(output.items, output.last_evaluated_key)
+                }
+            })
+            .left_stream(),
+            Err(err) => failed(err).right_stream(),
+        }
+    }
+
+    /// Every item, fetched page by page as the stream is polled.
+    pub fn into_stream(self) -> impl Stream<Item = Result<T, Error>> {
+        page_items(self.into_pages())
+    }
+
+    /// Runs the scan, following every page, and decodes each item.
+    pub async fn send(self) -> Result<Vec<T>, Error> {
+        self.into_stream().try_collect().await
     }
 }
 
 //
 // ─── UPDATE BUILDER ─────────────────────────────────────────────────────────────
 //
+/// One change to a stored attribute.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeUpdate {
+    Set {
+        attribute: String,
+        value: Av,
+    },
+    Remove {
+        attribute: String,
+    },
+    /// Adds a number to the attribute atomically, starting from zero if it
+    /// is missing.
+    Add {
+        attribute: String,
+        value: Av,
+    },
+}
+
+impl AttributeUpdate {
+    /// Values that serialize to null, such as `None`, remove the attribute
+    /// instead, since a sparse attribute is absent rather than null.
+    pub fn set<V: Serialize>(attribute: &str, value: &V) -> Self {
+        match serialize_attribute(value) {
+            Av::Null(_) => Self::remove(attribute),
+            value => AttributeUpdate::Set {
+                attribute: attribute.to_owned(),
+                value,
+            },
+        }
+    }
+
+    /// Sets a `Vec<u8>` field as a binary attribute, the way entities store it,
+    /// rather than the list of numbers `set` would write.
+    pub fn set_bytes(attribute: &str, bytes: &[u8]) -> Self {
+        AttributeUpdate::Set {
+            attribute: attribute.to_owned(),
+            value: binary_attribute(bytes),
+        }
+    }
+
+    pub fn remove(attribute: &str) -> Self {
+        AttributeUpdate::Remove {
+            attribute: attribute.to_owned(),
+        }
+    }
+
+    pub fn increment(attribute: &str, by: impl std::fmt::Display) -> Self {
+        AttributeUpdate::Add {
+            attribute: attribute.to_owned(),
+            value: Av::N(by.to_string()),
+        }
+    }
+
+    /// Adds `by` negated, so a negative amount, even the most negative
+    /// integer, counts up.
+    pub fn decrement(attribute: &str, by: impl CounterAmount) -> Self {
+        AttributeUpdate::Add {
+            attribute: attribute.to_owned(),
+            value: Av::N(by.negated()),
+        }
+    }
+
+    pub fn attribute(&self) -> &str {
+        match self {
+            AttributeUpdate::Set { attribute, .. }
+            | AttributeUpdate::Remove { attribute }
+            | AttributeUpdate::Add { attribute, .. } => attribute,
+        }
+    }
+}
+
+/// A number a counter can be decremented by.
+pub trait CounterAmount {
+    /// The amount with its sign flipped, as a DynamoDB number. Zero stays `0`.
+    fn negated(self) -> String;
+}
+
+macro_rules! counter_amount_through_i128 {
+    ($($ty:ty),*) => {$(
+        impl CounterAmount for $ty {
+            fn negated(self) -> String {
+                (-(self as i128)).to_string()
+            }
+        }
+    )*};
+}
+
+counter_amount_through_i128!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl CounterAmount for i128 {
+    fn negated(self) -> String {
+        match self.checked_neg() {
+            Some(negated) => negated.to_string(),
+            None => self.unsigned_abs().to_string(),
+        }
+    }
+}
+
+impl CounterAmount for u128 {
+    fn negated(self) -> String {
+        match i128::try_from(self) {
+            Ok(amount) => (-amount).to_string(),
+            Err(_) => format!("-{self}"),
+        }
+    }
+}
+
+macro_rules! counter_amount_float {
+    ($($ty:ty),*) => {$(
+        impl CounterAmount for $ty {
+            fn negated(self) -> String {
+                // `-0.0` prints as `-0`
+                if self == 0.0 { 0.0 } else { -self }.to_string()
+            }
+        }
+    )*};
+}
+
+counter_amount_float!(f32, f64);
+
+/// An `UpdateExpression` with its placeholders.
+pub(crate) struct UpdateExpression {
+    pub(crate) expression: String,
+    pub(crate) names: HashMap<String, String>,
+    pub(crate) values: Item,
+}
+
+impl UpdateExpression {
+    /// Gives each attribute its own `#uN` name and `:uN` value, so attribute
+    /// names never clash with reserved words.
+    pub(crate) fn new(updates: &[AttributeUpdate]) -> Self {
+        let mut names = HashMap::new();
+        let mut values = Item::new();
+        let mut sets = vec![];
+        let mut removes = vec![];
+        let mut adds = vec![];
+        for (i, update) in updates.iter().enumerate() {
+            let name = format!("#u{i}");
+            names.insert(name.clone(), update.attribute().to_owned());
+            match update {
+                AttributeUpdate::Set { value, .. } => {
+                    let placeholder = format!(":u{i}");
+                    values.insert(placeholder.clone(), value.clone());
+                    sets.push(format!("{name} = {placeholder}"));
+                }
+                AttributeUpdate::Remove { .. } => removes.push(name),
+                AttributeUpdate::Add { value, .. } => {
+                    let placeholder = format!(":u{i}");
+                    values.insert(placeholder.clone(), value.clone());
+                    adds.push(format!("{name} {placeholder}"));
+                }
+            }
+        }
+
+        let mut clauses = vec![];
+        if !sets.is_empty() {
+            clauses.push(format!("SET {}", sets.join(", ")));
+        }
+        if !removes.is_empty() {
+            clauses.push(format!("REMOVE {}", removes.join(", ")));
+        }
+        if !adds.is_empty() {
+            clauses.push(format!("ADD {}", adds.join(", ")));
+        }
+        UpdateExpression {
+            expression: clauses.join(" "),
+            names,
+            values,
+        }
+    }
+}
+
+/// Rejects updates that could not take effect: an empty update, one that sets
+/// a derived attribute directly rather than the fields it is built from, or one
+/// to a field of an enum that feeds a derived attribute.
+fn check_updates<T: Entity2>(schema: &SchemaV2, updates: &[AttributeUpdate]) -> Result<(), Error> {
+    if updates.is_empty() {
+        return Err(Error::InvalidRequest(
+            "an update needs at least one change".to_string(),
+        ));
+    }
+    // The key does not tell which variant is stored, so the attributes a
+    // variant derives from the field could not be rewritten with it
+    for variant in T::variant_schemas() {
+        let segment_fields = variant.segment_field_names().collect::<Vec<_>>();
+        if let Some(field) = updates
+            .iter()
+            .map(AttributeUpdate::attribute)
+            .find(|attribute| segment_fields.contains(attribute))
+        {
+            return Err(Error::InvalidRequest(format!(
+                "`{field}` feeds a derived attribute of an enum variant, so it can only \
+                 change by writing the whole entity"
+            )));
+        }
+    }
+    if let Some(attribute) = &schema.version_attribute
+        && updates.iter().any(|update| update.attribute() == attribute)
+    {
+        return Err(Error::InvalidRequest(format!(
+            "`{attribute}` is the entity's version and only changes with each write"
+        )));
+    }
+    let segment_fields = schema.segment_field_names().collect::<Vec<_>>();
+    let derived = schema
+        .composite_defs()
+        .map(|(name, _)| name)
+        .chain(schema.static_defs().map(|(name, _)| name))
+        .collect::<Vec<_>>();
+    match updates
+        .iter()
+        .map(AttributeUpdate::attribute)
+        .find(|attribute| !segment_fields.contains(attribute) && derived.contains(attribute))
+    {
+        Some(attribute) => Err(Error::InvalidRequest(format!(
+            "`{attribute}` is derived from the entity's fields and cannot be set directly"
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Whether `updates` change a field the table's pk or sk is built from.
+fn changes_table_key(schema: &SchemaV2, updates: &[AttributeUpdate]) -> bool {
+    let table_keys = schema.key_attribute_names().collect::<Vec<_>>();
+    schema
+        .composite_defs()
+        .filter(|(name, _)| table_keys.contains(name))
+        .flat_map(|(_, composite)| &composite.segments)
+        .any(|segment| {
+            updates
+                .iter()
+                .any(|update| update.attribute() == segment.struct_field_name)
+        })
+}
+
+/// The changes to derived attributes that keep them in step with `updates`,
+/// which are keyed by field name and leave the table key alone.
+///
+/// Every composite attribute built from an updated field is rendered again,
+/// so all of its fields must be in `updates`.
+fn derived_updates<T: Entity2>(updates: &[AttributeUpdate]) -> Result<Vec<AttributeUpdate>, Error> {
+    let schema = T::get_schema();
+    let updated: HashMap<&str, &AttributeUpdate> = updates
+        .iter()
+        .map(|update| (update.attribute(), update))
+        .collect();
+    let mut derived = vec![];
+    for (attribute_name, composite) in schema.composite_defs() {
+        let fed = composite
+            .segments
+            .iter()
+            .any(|segment| updated.contains_key(segment.struct_field_name.as_str()));
+        if !fed {
+            continue;
+        }
+        // A counter that is its own bare key, like `#[nk] followers`
+        if let Some(AttributeUpdate::Add { .. }) = updated.get(attribute_name)
+            && composite.bare_field() == Some(attribute_name)
+        {
+            continue;
+        }
+
+        let mut values = HashMap::new();
+        let mut present = true;
+        for segment in &composite.segments {
+            let field = segment.struct_field_name.as_str();
+            match updated.get(field) {
+                Some(AttributeUpdate::Set { value, .. }) => {
+                    values.insert(field.to_string(), T::segment_value(field, value)?);
+                }
+                Some(AttributeUpdate::Remove { .. }) if segment.optional => present = false,
+                Some(AttributeUpdate::Remove { .. }) => {
+                    return Err(Error::InvalidRequest(format!(
+                        "`{field}` feeds `{attribute_name}` and cannot be removed"
+                    )));
+                }
+                Some(AttributeUpdate::Add { .. }) => {
+                    return Err(Error::InvalidRequest(format!(
+                        "`{field}` feeds `{attribute_name}` and cannot be used as a counter"
+                    )));
+                }
+                None => {
+                    return Err(Error::InvalidRequest(format!(
+                        "updating `{attribute_name}` also needs `{field}`"
+                    )));
+                }
+            }
+        }
+
+        let update = match (present, composite.bare_field()) {
+            (false, _) => AttributeUpdate::remove(attribute_name),
+            // A bare key keeps the field's type, as in `to_attribute_map`
+            (true, Some(field)) => match updated[field] {
+                AttributeUpdate::Set { value, .. } => AttributeUpdate::Set {
+                    attribute: attribute_name.to_string(),
+                    value: value.clone(),
+                },
+                _ => AttributeUpdate::remove(attribute_name),
+            },
+            (true, None) => AttributeUpdate::Set {
+                attribute: attribute_name.to_string(),
+                value: Av::S(
+                    composite
+                        .render(&values, schema.delimiter)
+                        .expect("every segment has a value"),
+                ),
+            },
+        };
+        derived.push(update);
+    }
+    Ok(derived)
+}
+
+/// Changes attributes of an existing item. The item must already exist, so an
+/// update never creates a partial item. Derived attributes built from the
+/// updated fields are rewritten along with them.
+///
+/// Changing a field of the table's pk or sk moves the item: it is read, updated
+/// locally and swapped for the re-keyed item in one transaction. Only a
+/// `#[version]` field protects a move against writes made since the read: the
+/// move then fails with [`Error::VersionConflict`]. Without one, such a write is
+/// overwritten unless the caller's condition rules it out.
 pub struct UpdateBuilder<T> {
-    pub partition_key: Option<String>,
-    pub updates: Vec<Box<dyn Fn(&mut T) + 'static>>,
+    pub client: Client,
+    pub key: Item,
+    pub updates: Vec<AttributeUpdate>,
+    pub condition: Option<Condition<T>>,
+    /// The version the update is based on, required for versioned entities.
+    pub expected_version: Option<u64>,
+    pub tables: TableConfig,
+    pub _marker: std::marker::PhantomData<T>,
 }
 
-impl<T: Debug + Default> UpdateBuilder<T> {
-    pub fn where_partition_key(mut self, key: &str) -> Self {
-        self.partition_key = Some(key.to_owned());
+impl<T: Entity2> UpdateBuilder<T> {
+    pub fn new(key: Item, client: Client) -> Self {
+        UpdateBuilder {
+            client,
+            key,
+            updates: vec![],
+            condition: None,
+            expected_version: None,
+            tables: TableConfig::default(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Resolves the table through `tables` instead of the entity's own.
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.tables = tables;
         self
     }
 
-    pub fn send(self) {
-        println!(
-            "Update entity at pk={:?} with {} update(s)",
-            self.partition_key,
-            self.updates.len()
-        );
-        let mut entity = T::default();
-        for f in self.updates {
-            f(&mut entity);
+    /// Only updates if `condition` also holds for the stored item, failing
+    /// with [`Error::ConditionFailed`] otherwise.
+    pub fn condition(mut self, condition: Condition<T>) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Only updates if the stored item is still at `version`, the version of
+    /// the entity the update is based on. Failing that, the update fails with
+    /// [`Error::VersionConflict`].
+    pub fn expect_version(mut self, version: u64) -> Self {
+        self.expected_version = Some(version);
+        self
+    }
+
+    fn version(&self, schema: &SchemaV2) -> Result<Option<ExpectedVersion>, Error> {
+        let Some(attribute) = &schema.version_attribute else {
+            return Ok(None);
+        };
+        match self.expected_version {
+            Some(version) => Ok(Some(ExpectedVersion {
+                attribute: attribute.clone(),
+                version,
+            })),
+            None => Err(Error::InvalidRequest(format!(
+                "`{attribute}` versions the entity, so an update needs `expect_version`"
+            ))),
+        }
+    }
+
+    /// The item must exist at the expected version, and meet the caller's
+    /// condition if there is one.
+    fn guard(&self, version: Option<&ExpectedVersion>) -> Condition<T> {
+        let mut guard = Condition::item_exists();
+        if let Some(expected) = version {
+            guard = guard.and(expected.condition());
         }
-        println!("Updated entity would be: {:?}", entity);
+        match &self.condition {
+            Some(condition) => guard.and(condition.clone()),
+            None => guard,
+        }
+    }
+
+    pub fn set<V: Serialize>(mut self, attribute: &str, value: &V) -> Self {
+        self.updates.push(AttributeUpdate::set(attribute, value));
+        self
+    }
+
+    pub fn set_bytes(mut self, attribute: &str, bytes: &[u8]) -> Self {
+        self.updates
+            .push(AttributeUpdate::set_bytes(attribute, bytes));
+        self
+    }
+
+    pub fn remove(mut self, attribute: &str) -> Self {
+        self.updates.push(AttributeUpdate::remove(attribute));
+        self
+    }
+
+    /// `UpdateItem` for updates that keep the item's key.
+    fn prepare(self, version: Option<ExpectedVersion>) -> Result<Write, Error> {
+        let derived = derived_updates::<T>(&self.updates)?;
+        let guard = self.guard(version.as_ref());
+        // A derived attribute sharing a field's name takes the rendered value
+        let mut updates = self.updates;
+        updates.retain(|update| {
+            !derived
+                .iter()
+                .any(|derived| derived.attribute() == update.attribute())
+        });
+        updates.extend(derived);
+        if let Some(expected) = &version {
+            updates.push(AttributeUpdate::increment(&expected.attribute, 1));
+        }
+        let UpdateExpression {
+            expression,
+            names,
+            values,
+        } = UpdateExpression::new(&updates);
+
+        let write = Write {
+            item: self.key,
+            update_expression: Some(expression),
+            condition: None,
+            names,
+            values,
+            version,
+        };
+        Ok(write.with_condition(Some(&guard), &T::get_schema()))
+    }
+
+    /// The update as part of a transaction, which has no room for moving the
+    /// item.
+    pub(crate) fn prepare_in_transaction(self) -> Result<Write, Error> {
+        let schema = T::get_schema();
+        check_updates::<T>(&schema, &self.updates)?;
+        if changes_table_key(&schema, &self.updates) {
+            return Err(Error::InvalidRequest(
+                "an update that changes the item's key cannot be part of a transaction".to_string(),
+            ));
+        }
+        let version = self.version(&schema)?;
+        self.prepare(version)
+    }
+
+    /// Re-keys the item, returning it as it is stored afterwards.
+    async fn send_move(self, version: Option<ExpectedVersion>) -> Result<T, Error> {
+        use aws_sdk_dynamodb::types::{Delete, Put, TransactWriteItem};
+
+        let table = self.tables.resolve::<T>()?;
+        let old = self
+            .client
+            .get_item()
+            .table_name(&table)
+            .set_key(Some(self.key.clone()))
+            .consistent_read(true)
+            .send()
+            .await?
+            .item
+            .ok_or(Error::ConditionFailed)?;
+        let entity = T::from_attribute_map(&old)?;
+        let schema = entity.schema();
+
+        let segment_fields = schema.segment_field_names().collect::<Vec<_>>();
+        let mut fields = entity.field_attributes();
+        let mut values = entity.segment_values()?;
+        for update in &self.updates {
+            let field = update.attribute();
+            if !fields.contains_key(field) {
+                return Err(Error::InvalidRequest(format!(
+                    "`{field}` is not a field of the entity"
+                )));
+            }
+            match update {
+                AttributeUpdate::Set { value, .. } => {
+                    if segment_fields.contains(&field) {
+                        values.insert(field.to_string(), T::segment_value(field, value)?);
+                    }
+                    fields.insert(field.to_string(), value.clone());
+                }
+                AttributeUpdate::Remove { .. } => {
+                    values.remove(field);
+                    fields.remove(field);
+                }
+                AttributeUpdate::Add { .. } => {
+                    return Err(Error::InvalidRequest(format!(
+                        "`{field}` cannot be counted while the item's key changes"
+                    )));
+                }
+            }
+        }
+        if let Some(expected) = &version {
+            let found = stored_version(&old, &expected.attribute).ok();
+            if found != Some(expected.version) {
+                return Err(Error::VersionConflict {
+                    expected: expected.version,
+                    found,
+                });
+            }
+            fields.insert(expected.attribute.clone(), expected.next());
+        }
+        let key = schema.key_attributes(&fields, &values);
+        let mut item = fields;
+        schema.merge_key_attributes(&mut item, &values);
+
+        // Read back to make sure every change survives the round trip
+        let moved = T::from_attribute_map(&item)?;
+        let stored = moved.field_attributes();
+        for update in &self.updates {
+            if let AttributeUpdate::Set { attribute, value } = update
+                && stored.get(attribute) != Some(value)
+            {
+                return Err(Error::InvalidRequest(format!(
+                    "`{attribute}` cannot be stored as {value:?}"
+                )));
+            }
+        }
+
+        // The old item must still exist, at the version read if there is one,
+        // and meet the caller's condition. The new key must be free
+        let guard = self.guard(version.as_ref());
+        let return_old = version.as_ref().map(|_| ALL_OLD);
+        let put = |condition: Condition<T>, return_old| {
+            let mut names = HashMap::new();
+            let mut values = Item::new();
+            let condition = condition.expression(&schema, &mut names, &mut values);
+            Put::builder()
+                .table_name(&table)
+                .set_item(Some(item.clone()))
+                .condition_expression(condition)
+                .set_expression_attribute_names(Some(names))
+                .set_expression_attribute_values((!values.is_empty()).then_some(values))
+                .set_return_values_on_condition_check_failure(return_old)
+                .build()
+                .expect("table name and item are set")
+        };
+        // DynamoDB rejects two actions on one item, so a key set to its current
+        // value is a plain overwrite
+        let actions = if key == self.key {
+            vec![
+                TransactWriteItem::builder()
+                    .put(put(guard, return_old))
+                    .build(),
+            ]
+        } else {
+            let mut names = HashMap::new();
+            let mut values = Item::new();
+            let condition = guard.expression(&schema, &mut names, &mut values);
+            let delete = Delete::builder()
+                .table_name(&table)
+                .set_key(Some(self.key))
+                .condition_expression(condition)
+                .set_expression_attribute_names(Some(names))
+                .set_expression_attribute_values((!values.is_empty()).then_some(values))
+                .set_return_values_on_condition_check_failure(return_old)
+                .build()
+                .expect("table name and key are set");
+            vec![
+                TransactWriteItem::builder().delete(delete).build(),
+                TransactWriteItem::builder()
+                    .put(put(Condition::item_not_exists(), None))
+                    .build(),
+            ]
+        };
+        self.client
+            .transact_write_items()
+            .set_transact_items(Some(actions))
+            .send()
+            .await
+            .map_err(|err| Error::from_versioned(err, version.as_ref()))?;
+        Ok(moved)
+    }
+
+    /// Runs the update, fetching the item as it is afterwards if `returning`.
+    async fn run(self, returning: bool) -> Result<Option<T>, Error> {
+        let schema = T::get_schema();
+        check_updates::<T>(&schema, &self.updates)?;
+        let version = self.version(&schema)?;
+        if changes_table_key(&schema, &self.updates) {
+            return self.send_move(version).await.map(Some);
+        }
+        let return_values = returning.then_some(aws_sdk_dynamodb::types::ReturnValue::AllNew);
+        match self.update_item(version, return_values).await? {
+            Some(item) if returning => Ok(Some(T::from_attribute_map(&item)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// `UpdateItem` for updates that keep the item's key, returning the
+    /// attributes `return_values` asks for.
+    async fn update_item(
+        self,
+        version: Option<ExpectedVersion>,
+        return_values: Option<aws_sdk_dynamodb::types::ReturnValue>,
+    ) -> Result<Option<Item>, Error> {
+        let client = self.client.clone();
+        let table = self.tables.resolve::<T>()?;
+        let write = self.prepare(version)?;
+        let return_old = write.return_old();
+        let output = client
+            .update_item()
+            .table_name(table)
+            .set_key(Some(write.item))
+            .set_update_expression(write.update_expression)
+            .set_condition_expression(write.condition)
+            .set_expression_attribute_names(non_empty(write.names))
+            .set_expression_attribute_values(non_empty(write.values))
+            .set_return_values_on_condition_check_failure(return_old)
+            .set_return_values(return_values)
+            .send()
+            .await
+            .map_err(|err| Error::from_versioned(err, write.version.as_ref()))?;
+        Ok(output.attributes)
+    }
+
+    pub async fn send(self) -> Result<(), Error> {
+        self.run(false).await.map(|_| ())
+    }
+
+    /// Applies the update and returns the item as it is afterwards.
+    pub async fn send_returning(self) -> Result<T, Error> {
+        self.run(true)
+            .await?
+            .ok_or_else(|| Error::InvalidRequest("the update returned no item".to_string()))
+    }
+
+    /// Applies the update and returns the new value of `counter`, which the
+    /// update must increment or decrement. Only the updated attributes come
+    /// back (`UPDATED_NEW`), not the whole item.
+    pub async fn send_counter<V: DeserializeOwned>(self, counter: &str) -> Result<V, Error> {
+        let schema = T::get_schema();
+        check_updates::<T>(&schema, &self.updates)?;
+        let counted = self.updates.iter().any(|update| {
+            matches!(update, AttributeUpdate::Add { attribute, .. } if attribute == counter)
+        });
+        if !counted {
+            return Err(Error::InvalidRequest(format!(
+                "`{counter}` is not counted by this update"
+            )));
+        }
+        if changes_table_key(&schema, &self.updates) {
+            return Err(Error::InvalidRequest(format!(
+                "`{counter}` cannot be counted while the item's key changes"
+            )));
+        }
+        let version = self.version(&schema)?;
+        let item = self
+            .update_item(
+                version,
+                Some(aws_sdk_dynamodb::types::ReturnValue::UpdatedNew),
+            )
+            .await?
+            .unwrap_or_default();
+        let value = item
+            .get(counter)
+            .ok_or_else(|| Error::InvalidRequest(format!("the update returned no `{counter}`")))?;
+        Ok(attribute_field_value(value, counter)?)
     }
 }
 
@@ -103,6 +1407,12 @@ impl<T> UpdateBuilderWithSetters<T> {
     }
 }
 
+impl<T> From<UpdateBuilderWithSetters<T>> for UpdateBuilder<T> {
+    fn from(builder: UpdateBuilderWithSetters<T>) -> Self {
+        builder.inner
+    }
+}
+
 //
 // ─── TRAIT USED BY MACROS TO AVOID ORPHAN RULE ──────────────────────────────────
 //
@@ -110,47 +1420,480 @@ pub trait HasInner<T> {
     fn inner_mut(&mut self) -> &mut UpdateBuilder<T>;
 }
 
-impl<T: Debug + Default> UpdateBuilderWithSetters<T> {
-    // by-value -> returns Self (so you can chain on rvalues)
-    pub fn where_partition_key(mut self, key: &str) -> Self {
-        self.inner = self.inner.where_partition_key(key);
+impl<T> HasInner<T> for UpdateBuilder<T> {
+    fn inner_mut(&mut self) -> &mut UpdateBuilder<T> {
         self
     }
+}
 
-    // by-value -> consumes at the end of the chain
-    pub fn send(self) {
-        self.inner.send();
+impl<T> HasInner<T> for UpdateBuilderWithSetters<T> {
+    fn inner_mut(&mut self) -> &mut UpdateBuilder<T> {
+        &mut self.inner
     }
 }
 
-#[derive(Debug)]
-pub struct SchemaV2 {
-    pub partition_key_def: KeyDef<CompositeAttributeValue>,
-    pub sort_key_def: Option<KeyDef<AttributeValue>>,
-    pub non_key_defs: Vec<KeyDef<AttributeValue>>,
-}
+impl<T: Entity2> UpdateBuilderWithSetters<T> {
+    pub fn condition(mut self, condition: Condition<T>) -> Self {
+        self.inner = self.inner.condition(condition);
+        self
+    }
 
-#[derive(Debug)]
-pub struct KeyDef<V> {
-    pub attribute_name: String,
-    pub attribute_value: V,
-}
+    pub fn expect_version(mut self, version: u64) -> Self {
+        self.inner = self.inner.expect_version(version);
+        self
+    }
 
-#[derive(Debug)]
-pub enum AttributeValue {
-    Static(String),
-    Composite(CompositeAttributeValue),
-}
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.inner = self.inner.tables(tables);
+        self
+    }
+
+    // by-value -> consumes at the end of the chain
+    pub async fn send(self) -> Result<(), Error> {
+        self.inner.send().await
+    }
+
+    pub async fn send_returning(self) -> Result<T, Error> {
+        self.inner.send_returning().await
+    }
 
-#[derive(Debug)]
-pub struct CompositeAttributeValue {
-    pub segments: Vec<Segment>,
-    pub prefix: Option<String>,
-    pub suffix: Option<String>,
+    pub async fn send_counter<V: DeserializeOwned>(self, counter: &str) -> Result<V, Error> {
+        self.inner.send_counter(counter).await
+    }
 }
 
-#[derive(Debug)]
-pub struct Segment {
-    pub struct_field_name: String,
-    pub prefix: Option<String>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `Segment` of a plain field
+    fn segment(field: &str, prefix: Option<&str>) -> Segment {
+        Segment {
+            struct_field_name: field.to_string(),
+            prefix: prefix.map(str::to_string),
+            encoding: SegmentEncoding::Plain,
+            format: None,
+            optional: false,
+            scalar_type: ScalarType::S,
+        }
+    }
+
+    fn composite(segments: Vec<Segment>) -> CompositeAttributeValue {
+        CompositeAttributeValue {
+            segments,
+            prefix: None,
+            suffix: None,
+        }
+    }
+
+    fn key_def<V>(attribute_name: &str, attribute_value: V) -> KeyDef<V> {
+        KeyDef {
+            attribute_name: attribute_name.to_string(),
+            attribute_value,
+        }
+    }
+
+    /// What `#[derive(Dynodmize)]` generates for the README's `UserCount`,
+    /// whose `followers` counter is also a bare `#[nk]`.
+    #[derive(Debug, Serialize)]
+    struct UserCount {
+        user_id: u32,
+        followers: u64,
+    }
+
+    impl Entity2 for UserCount {
+        fn get_schema() -> SchemaV2 {
+            SchemaV2 {
+                partition_key_def: key_def("pk", composite(vec![segment("user_id", Some("u"))])),
+                sort_key_def: Some(key_def("sk", AttributeValue::Static("count".to_string()))),
+                non_key_defs: vec![key_def(
+                    "followers",
+                    AttributeValue::Composite(composite(vec![Segment {
+                        scalar_type: ScalarType::N,
+                        ..segment("followers", None)
+                    }])),
+                )],
+                index_defs: vec![],
+                delimiter: DEFAULT_DELIMITER,
+                version_attribute: None,
+                table_name: None,
+            }
+        }
+
+        fn segment_values(&self) -> Result<HashMap<String, String>, ItemError> {
+            Ok(HashMap::from([
+                ("user_id".to_string(), self.user_id.to_string()),
+                ("followers".to_string(), self.followers.to_string()),
+            ]))
+        }
+
+        fn segment_value(field: &str, value: &Av) -> Result<String, ItemError> {
+            match field {
+                "user_id" => Ok(attribute_field_value::<u32>(value, field)?.to_string()),
+                "followers" => Ok(attribute_field_value::<u64>(value, field)?.to_string()),
+                _ => Err(ItemError::InvalidField {
+                    field: field.to_string(),
+                    message: "does not feed a derived attribute".to_string(),
+                }),
+            }
+        }
+
+        fn field_attributes(&self) -> Item {
+            serialize_attributes(self)
+        }
+
+        fn from_item(item: &serde_json::Value) -> Result<Self, ItemError> {
+            let segments = Self::get_schema().decode_segments(item)?;
+            Ok(UserCount {
+                user_id: segment_value(&segments, "user_id")?,
+                followers: segment_value(&segments, "followers")?,
+            })
+        }
+    }
+
+    #[test]
+    fn to_item_keeps_bare_keys_typed_like_to_attribute_map() {
+        let count = UserCount {
+            user_id: 123,
+            followers: 3,
+        };
+        let item = count.to_item().unwrap();
+        assert_eq!(item["pk"], serde_json::json!("u#123"));
+        assert_eq!(item["sk"], serde_json::json!("count"));
+        assert_eq!(item["followers"], serde_json::json!(3));
+        assert_eq!(
+            item,
+            attribute_map_to_json(&count.to_attribute_map().unwrap())
+        );
+        let read = UserCount::from_item(&item).unwrap();
+        assert_eq!((read.user_id, read.followers), (123, 3));
+    }
+
+    fn added(update: AttributeUpdate) -> Av {
+        match update {
+            AttributeUpdate::Add { value, .. } => value,
+            other => panic!("not an ADD: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decrement_negates_the_amount() {
+        let decrement = |by: i64| added(AttributeUpdate::decrement("balance", by));
+        assert_eq!(decrement(3), Av::N("-3".to_string()));
+        assert_eq!(decrement(-3), Av::N("3".to_string()));
+        assert_eq!(decrement(0), Av::N("0".to_string()));
+        assert_eq!(
+            decrement(i64::MIN),
+            Av::N("9223372036854775808".to_string())
+        );
+        assert_eq!(
+            added(AttributeUpdate::decrement("ratio", -0.5)),
+            Av::N("0.5".to_string())
+        );
+        assert_eq!(
+            added(AttributeUpdate::decrement("ratio", 0.0)),
+            Av::N("0".to_string())
+        );
+        assert_eq!(
+            added(AttributeUpdate::decrement("views", u64::MAX)),
+            Av::N("-18446744073709551615".to_string())
+        );
+        assert_eq!(
+            added(AttributeUpdate::decrement("views", i128::MIN)),
+            Av::N("170141183460469231731687303715884105728".to_string())
+        );
+    }
+
+    /// Posts under `u#{user_id}` and `p#{post_id}`, listed by topic and likes
+    /// in `gsi1sk`, with a sparse `draft` attribute while `draft_of` is set.
+    #[derive(Debug, Serialize)]
+    struct Post {
+        user_id: u32,
+        post_id: u32,
+        topic: String,
+        likes: u32,
+        draft_of: Option<u32>,
+        body: String,
+        version: u64,
+    }
+
+    impl Entity2 for Post {
+        fn get_schema() -> SchemaV2 {
+            let gsi1sk = composite(vec![
+                segment("topic", Some("t")),
+                segment("likes", Some("l")),
+            ]);
+            let draft = composite(vec![Segment {
+                optional: true,
+                ..segment("draft_of", Some("d"))
+            }]);
+            SchemaV2 {
+                partition_key_def: key_def("pk", composite(vec![segment("user_id", Some("u"))])),
+                sort_key_def: Some(key_def(
+                    "sk",
+                    AttributeValue::Composite(composite(vec![segment("post_id", Some("p"))])),
+                )),
+                non_key_defs: vec![
+                    key_def("gsi1sk", AttributeValue::Composite(gsi1sk)),
+                    key_def("draft", AttributeValue::Composite(draft)),
+                    key_def("type", AttributeValue::Static("post".to_string())),
+                ],
+                index_defs: vec![],
+                delimiter: DEFAULT_DELIMITER,
+                version_attribute: Some("version".to_string()),
+                table_name: None,
+            }
+        }
+
+        fn segment_values(&self) -> Result<HashMap<String, String>, ItemError> {
+            let mut values = HashMap::from([
+                ("user_id".to_string(), self.user_id.to_string()),
+                ("post_id".to_string(), self.post_id.to_string()),
+                ("topic".to_string(), self.topic.clone()),
+                ("likes".to_string(), self.likes.to_string()),
+            ]);
+            if let Some(draft_of) = self.draft_of {
+                values.insert("draft_of".to_string(), draft_of.to_string());
+            }
+            Ok(values)
+        }
+
+        fn segment_value(field: &str, value: &Av) -> Result<String, ItemError> {
+            match field {
+                "topic" => Ok(attribute_field_value::<String>(value, field)?),
+                _ => Ok(attribute_field_value::<u32>(value, field)?.to_string()),
+            }
+        }
+
+        fn field_attributes(&self) -> Item {
+            serialize_attributes(self)
+        }
+
+        fn from_item(_: &serde_json::Value) -> Result<Self, ItemError> {
+            unimplemented!("posts are not read in these tests")
+        }
+    }
+
+    /// An enum whose `Circle` variant derives its sk from `radius`, the way
+    /// `#[derive(Dynodmize)]` reports it through `variant_schemas`.
+    #[derive(Debug, Serialize)]
+    struct Shape;
+
+    impl Entity2 for Shape {
+        fn get_schema() -> SchemaV2 {
+            SchemaV2 {
+                sort_key_def: None,
+                ..Post::get_schema()
+            }
+        }
+
+        fn variant_schemas() -> Vec<SchemaV2> {
+            vec![SchemaV2 {
+                sort_key_def: Some(key_def(
+                    "sk",
+                    AttributeValue::Composite(composite(vec![segment("radius", Some("c"))])),
+                )),
+                ..Self::get_schema()
+            }]
+        }
+
+        fn segment_values(&self) -> Result<HashMap<String, String>, ItemError> {
+            Ok(HashMap::new())
+        }
+
+        fn segment_value(_: &str, value: &Av) -> Result<String, ItemError> {
+            attribute_field_value::<String>(value, "radius")
+        }
+
+        fn field_attributes(&self) -> Item {
+            Item::new()
+        }
+
+        fn from_item(_: &serde_json::Value) -> Result<Self, ItemError> {
+            Ok(Shape)
+        }
+    }
+
+    fn rejected(result: Result<impl std::fmt::Debug, Error>) -> String {
+        match result {
+            Err(Error::InvalidRequest(message)) => message,
+            other => panic!("expected an invalid request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn derived_updates_rewrite_the_attributes_built_from_updated_fields() {
+        let updates = [
+            AttributeUpdate::set("likes", &3),
+            AttributeUpdate::set("topic", &"rust"),
+            AttributeUpdate::set("body", &"hello"),
+        ];
+        assert_eq!(
+            derived_updates::<Post>(&updates).unwrap(),
+            vec![AttributeUpdate::Set {
+                attribute: "gsi1sk".to_string(),
+                value: Av::S("t#rust#l#3".to_string()),
+            }]
+        );
+        assert_eq!(
+            derived_updates::<Post>(&[AttributeUpdate::set("body", &"hello")]).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn derived_updates_remove_sparse_attributes_with_their_field() {
+        assert_eq!(
+            derived_updates::<Post>(&[AttributeUpdate::set("draft_of", &None::<u32>)]).unwrap(),
+            vec![AttributeUpdate::remove("draft")]
+        );
+    }
+
+    #[test]
+    fn derived_updates_keep_bare_keys_typed_and_leave_counters_alone() {
+        assert_eq!(
+            derived_updates::<UserCount>(&[AttributeUpdate::set("followers", &3)]).unwrap(),
+            vec![AttributeUpdate::Set {
+                attribute: "followers".to_string(),
+                value: Av::N("3".to_string()),
+            }]
+        );
+        assert_eq!(
+            derived_updates::<UserCount>(&[AttributeUpdate::increment("followers", 1)]).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn derived_updates_reject_partial_composite_updates() {
+        let message = rejected(derived_updates::<Post>(&[AttributeUpdate::set(
+            "topic", &"rust",
+        )]));
+        assert_eq!(message, "updating `gsi1sk` also needs `likes`");
+
+        let counted = [
+            AttributeUpdate::set("topic", &"rust"),
+            AttributeUpdate::increment("likes", 1),
+        ];
+        assert!(rejected(derived_updates::<Post>(&counted)).contains("as a counter"));
+
+        let removed = [
+            AttributeUpdate::remove("topic"),
+            AttributeUpdate::set("likes", &3),
+        ];
+        assert!(rejected(derived_updates::<Post>(&removed)).contains("cannot be removed"));
+    }
+
+    #[test]
+    fn check_updates_rejects_updates_that_cannot_take_effect() {
+        let check =
+            |updates: &[AttributeUpdate]| check_updates::<Post>(&Post::get_schema(), updates);
+        assert!(check(&[AttributeUpdate::set("body", &"hello")]).is_ok());
+        assert!(rejected(check(&[])).contains("at least one change"));
+        assert!(rejected(check(&[AttributeUpdate::set("version", &2)])).contains("version"));
+        for derived in ["gsi1sk", "type"] {
+            let message = rejected(check(&[AttributeUpdate::set(derived, &"x")]));
+            assert!(message.contains("cannot be set directly"), "{message}");
+        }
+    }
+
+    #[test]
+    fn check_updates_rejects_fields_of_enum_variants() {
+        let message = rejected(check_updates::<Shape>(
+            &Shape::get_schema(),
+            &[AttributeUpdate::set("radius", &"2")],
+        ));
+        assert!(message.contains("enum variant"), "{message}");
+        assert!(
+            check_updates::<Shape>(&Shape::get_schema(), &[AttributeUpdate::set("body", &"")])
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn update_expression_groups_clauses_and_numbers_placeholders_by_position() {
+        let UpdateExpression {
+            expression,
+            names,
+            values,
+        } = UpdateExpression::new(&[
+            AttributeUpdate::set("name", &"Ada"),
+            AttributeUpdate::remove("nickname"),
+            AttributeUpdate::increment("visits", 1),
+            AttributeUpdate::set("status", &"active"),
+        ]);
+        assert_eq!(
+            expression,
+            "SET #u0 = :u0, #u3 = :u3 REMOVE #u1 ADD #u2 :u2"
+        );
+        assert_eq!(
+            names,
+            HashMap::from(
+                [
+                    ("#u0", "name"),
+                    ("#u1", "nickname"),
+                    ("#u2", "visits"),
+                    ("#u3", "status")
+                ]
+                .map(|(placeholder, name)| (placeholder.to_string(), name.to_string()))
+            )
+        );
+        assert_eq!(
+            values,
+            Item::from([
+                (":u0".to_string(), Av::S("Ada".to_string())),
+                (":u2".to_string(), Av::N("1".to_string())),
+                (":u3".to_string(), Av::S("active".to_string())),
+            ])
+        );
+    }
+
+    fn client() -> Client {
+        Client::from_conf(
+            aws_sdk_dynamodb::Config::builder()
+                .behavior_version(aws_sdk_dynamodb::config::BehaviorVersion::latest())
+                .build(),
+        )
+    }
+
+    fn rendered<T>(
+        condition: &Condition<T>,
+        schema: &SchemaV2,
+    ) -> (String, HashMap<String, String>, Item) {
+        let mut names = HashMap::new();
+        let mut values = Item::new();
+        let expression = condition.expression(schema, &mut names, &mut values);
+        (expression, names, values)
+    }
+
+    #[test]
+    fn the_guard_of_a_move_is_the_item_its_version_and_the_callers_condition() {
+        let schema = Post::get_schema();
+        let update = UpdateBuilder::<Post>::new(Item::new(), client())
+            .set("post_id", &9)
+            .condition(Field::<Post, String>::new("body").eq("hello".to_string()))
+            .expect_version(4);
+        let version = update.version(&schema).unwrap();
+        assert_eq!(
+            rendered(&update.guard(version.as_ref()), &schema),
+            (
+                "((attribute_exists(#c0) AND #c1 = :c0) AND #c2 = :c1)".to_string(),
+                HashMap::from(
+                    [("#c0", "pk"), ("#c1", "version"), ("#c2", "body")]
+                        .map(|(placeholder, name)| (placeholder.to_string(), name.to_string()))
+                ),
+                Item::from([
+                    (":c0".to_string(), Av::N("4".to_string())),
+                    (":c1".to_string(), Av::S("hello".to_string())),
+                ]),
+            )
+        );
+
+        // Without a version or condition, no attribute of the old item is compared
+        let schema = UserCount::get_schema();
+        let update = UpdateBuilder::<UserCount>::new(Item::new(), client()).set("user_id", &9);
+        let (expression, _, values) = rendered(&update.guard(None), &schema);
+        assert_eq!(expression, "attribute_exists(#c0)");
+        assert!(values.is_empty());
+    }
 }
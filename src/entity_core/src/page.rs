@@ -0,0 +1,71 @@
+use crate::{Cursor, Entity2, Error, Item};
+use futures::stream::{self, Stream, TryStreamExt};
+use std::future::Future;
+
+/// One response of a paginated request, decoded into entities.
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Where the next page starts, or `None` once the last page has been read.
+    /// Pass it to `start_from` to resume the request.
+    pub last_evaluated_key: Option<Item>,
+}
+
+impl<T> Page<T> {
+    /// The `last_evaluated_key` as a token for API clients.
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.last_evaluated_key.clone().map(Cursor::new)
+    }
+}
+
+impl<T: Entity2> Page<T> {
+    pub(crate) fn decode(
+        items: Option<Vec<Item>>,
+        last_evaluated_key: Option<Item>,
+    ) -> Result<Self, Error> {
+        let items = items
+            .unwrap_or_default()
+            .iter()
+            .map(T::from_attribute_map)
+            .collect::<Result<_, _>>()?;
+        Ok(Page {
+            items,
+            last_evaluated_key,
+        })
+    }
+}
+
+/// Requests pages lazily, starting at `start_key` and following
+/// `last_evaluated_key` until a page comes back without one.
+pub(crate) fn pages<T, F, Fut>(
+    start_key: Option<Item>,
+    fetch: F,
+) -> impl Stream<Item = Result<Page<T>, Error>>
+where
+    F: FnMut(Option<Item>) -> Fut,
+    Fut: Future<Output = Result<Page<T>, Error>>,
+{
+    // `None` once there are no pages left
+    stream::try_unfold((fetch, Some(start_key)), |(mut fetch, next)| async move {
+        let Some(start_key) = next else {
+            return Ok(None);
+        };
+        let page = fetch(start_key).await?;
+        let next = page.last_evaluated_key.clone().map(Some);
+        Ok(Some((page, (fetch, next))))
+    })
+}
+
+/// Flattens pages into their items, in order.
+pub(crate) fn page_items<T>(
+    pages: impl Stream<Item = Result<Page<T>, Error>>,
+) -> impl Stream<Item = Result<T, Error>> {
+    pages
+        .map_ok(|page| stream::iter(page.items.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+/// A stream that only yields `err`, for requests that fail before being sent.
+pub(crate) fn failed<T>(err: Error) -> impl Stream<Item = Result<T, Error>> {
+    stream::once(async move { Err(err) })
+}
@@ -0,0 +1,578 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Separates the parts of a composite value unless the entity sets
+/// `#[dynodmize(delimiter = "...")]`.
+pub const DEFAULT_DELIMITER: char = '#';
+
+/// Starts a `%XX` escape inside a segment value. It cannot be used as a delimiter.
+pub const ESCAPE: char = '%';
+
+#[derive(Debug)]
+pub struct SchemaV2 {
+    pub partition_key_def: KeyDef<CompositeAttributeValue>,
+    pub sort_key_def: Option<KeyDef<AttributeValue>>,
+    /// Every other derived attribute, including index keys that are not also
+    /// table keys.
+    pub non_key_defs: Vec<KeyDef<AttributeValue>>,
+    pub index_defs: Vec<IndexDef>,
+    pub delimiter: char,
+    /// The `#[version]` field, incremented by every put and update, which are
+    /// only accepted against the version they were based on.
+    pub version_attribute: Option<String>,
+    /// The table from `#[dynodmize(table = "...")]`, before a [`TableConfig`]
+    /// applies its prefix and overrides.
+    ///
+    /// [`TableConfig`]: crate::TableConfig
+    pub table_name: Option<String>,
+}
+
+/// A secondary index and how its key attributes are composed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexDef {
+    pub index_name: String,
+    pub kind: IndexKind,
+    /// For a local index, the table's partition key.
+    pub partition_key_def: KeyDef<CompositeAttributeValue>,
+    pub sort_key_def: Option<KeyDef<AttributeValue>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    Global,
+    Local,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyDef<V> {
+    pub attribute_name: String,
+    pub attribute_value: V,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeValue {
+    Static(String),
+    Composite(CompositeAttributeValue),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompositeAttributeValue {
+    pub segments: Vec<Segment>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub struct_field_name: String,
+    pub prefix: Option<String>,
+    pub encoding: SegmentEncoding,
+    /// Strftime-style format for date and time fields, see [`crate::FormattedSegment`].
+    pub format: Option<String>,
+    /// Set for `Option` fields. The whole attribute is left out of the item
+    /// while the field is `None`, which keeps sparse indexes sparse.
+    pub optional: bool,
+    /// The type the field is written as, which a bare key keeps.
+    pub scalar_type: ScalarType,
+}
+
+/// The DynamoDB type of a key attribute.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScalarType {
+    /// Strings, and any field that is not a number or bytes.
+    #[default]
+    S,
+    N,
+    B,
+}
+
+/// How a segment value is written so that keys sort the way the field does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SegmentEncoding {
+    /// The field's `Display` output as is.
+    #[default]
+    Plain,
+    /// Unsigned integers left-padded with zeros to this many digits.
+    Pad(usize),
+    /// Signed or unsigned 64-bit integers, in a fixed-width form whose string
+    /// order matches numeric order.
+    SortableInt,
+    /// Floats, as the hex of their order-preserving bit pattern.
+    SortableFloat,
+}
+
+/// `10^19`, the offset that makes negative integers sort below zero.
+const NEGATIVE_OFFSET: i128 = 10_000_000_000_000_000_000;
+
+impl SegmentEncoding {
+    /// Encodes the field's string form. Values the encoding does not apply to,
+    /// such as a number wider than its padding, are left as they are.
+    pub fn encode<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        match self {
+            SegmentEncoding::Plain => Cow::Borrowed(value),
+            SegmentEncoding::Pad(width) => {
+                if value.bytes().all(|b| b.is_ascii_digit()) {
+                    Cow::Owned(format!("{value:0>width$}"))
+                } else {
+                    Cow::Borrowed(value)
+                }
+            }
+            SegmentEncoding::SortableInt => match value.parse::<i128>() {
+                Ok(n) if (0..=u64::MAX as i128).contains(&n) => Cow::Owned(format!("{n:020}")),
+                Ok(n) if n >= i64::MIN as i128 => {
+                    Cow::Owned(format!("-{:019}", NEGATIVE_OFFSET + n))
+                }
+                _ => Cow::Borrowed(value),
+            },
+            SegmentEncoding::SortableFloat => match value.parse::<f64>() {
+                Ok(f) => {
+                    let bits = f.to_bits();
+                    let sortable = if bits >> 63 == 1 {
+                        !bits
+                    } else {
+                        bits | 1 << 63
+                    };
+                    Cow::Owned(format!("{sortable:016X}"))
+                }
+                Err(_) => Cow::Borrowed(value),
+            },
+        }
+    }
+
+    /// Reverses [`SegmentEncoding::encode`], giving back a string the field's
+    /// `FromStr` accepts.
+    pub fn decode(&self, token: &str) -> Option<String> {
+        match self {
+            SegmentEncoding::Plain => Some(token.to_string()),
+            SegmentEncoding::Pad(_) => {
+                let trimmed = token.trim_start_matches('0');
+                Some(if trimmed.is_empty() && !token.is_empty() {
+                    "0".to_string()
+                } else {
+                    trimmed.to_string()
+                })
+            }
+            SegmentEncoding::SortableInt => match token.strip_prefix('-') {
+                Some(digits) => Some((digits.parse::<i128>().ok()? - NEGATIVE_OFFSET).to_string()),
+                None => Some(token.parse::<u64>().ok()?.to_string()),
+            },
+            SegmentEncoding::SortableFloat => {
+                let sortable = u64::from_str_radix(token, 16).ok()?;
+                let bits = if sortable >> 63 == 1 {
+                    sortable & !(1 << 63)
+                } else {
+                    !sortable
+                };
+                Some(f64::from_bits(bits).to_string())
+            }
+        }
+    }
+}
+
+impl SchemaV2 {
+    /// The secondary index called `index_name`.
+    pub fn index(&self, index_name: &str) -> Option<&IndexDef> {
+        self.index_defs
+            .iter()
+            .find(|index| index.index_name == index_name)
+    }
+
+    /// The table's key attributes, partition key first.
+    pub fn key_attribute_names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.partition_key_def.attribute_name.as_str()).chain(
+            self.sort_key_def
+                .iter()
+                .map(|key_def| key_def.attribute_name.as_str()),
+        )
+    }
+
+    /// Every attribute built from struct fields, partition key first.
+    pub fn composite_defs(&self) -> impl Iterator<Item = (&str, &CompositeAttributeValue)> {
+        let pk = &self.partition_key_def;
+        let others = self
+            .sort_key_def
+            .iter()
+            .chain(&self.non_key_defs)
+            .filter_map(|key_def| match &key_def.attribute_value {
+                AttributeValue::Composite(composite) => {
+                    Some((key_def.attribute_name.as_str(), composite))
+                }
+                AttributeValue::Static(_) => None,
+            });
+        std::iter::once((pk.attribute_name.as_str(), &pk.attribute_value)).chain(others)
+    }
+
+    /// Composite attributes that cannot be rendered from `values` because an
+    /// optional field is `None`. These are left out of the item.
+    pub fn sparse_attributes<'a>(
+        &'a self,
+        values: &'a HashMap<String, String>,
+    ) -> impl Iterator<Item = &'a str> {
+        self.composite_defs()
+            .filter(|(_, composite)| composite.render(values, self.delimiter).is_none())
+            .map(|(name, _)| name)
+    }
+
+    /// Every struct field that feeds a composite attribute.
+    pub fn segment_field_names(&self) -> impl Iterator<Item = &str> {
+        self.composite_defs()
+            .flat_map(|(_, composite)| &composite.segments)
+            .map(|segment| segment.struct_field_name.as_str())
+    }
+
+    /// Every attribute with a fixed value.
+    pub fn static_defs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.sort_key_def
+            .iter()
+            .chain(&self.non_key_defs)
+            .filter_map(|key_def| match &key_def.attribute_value {
+                AttributeValue::Static(value) => {
+                    Some((key_def.attribute_name.as_str(), value.as_str()))
+                }
+                AttributeValue::Composite(_) => None,
+            })
+    }
+
+    /// Renders every derived attribute from the string form of the struct fields.
+    /// Composite attributes whose fields are missing from `values` are left out.
+    pub fn render_attributes(&self, values: &HashMap<String, String>) -> Vec<(String, String)> {
+        let composites = self.composite_defs().filter_map(|(name, composite)| {
+            let value = composite.render(values, self.delimiter)?;
+            Some((name.to_string(), value))
+        });
+        let statics = self
+            .static_defs()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        composites.chain(statics).collect()
+    }
+}
+
+impl CompositeAttributeValue {
+    /// Joins the prefix, each segment's prefix and value, and the suffix with
+    /// `delimiter`. Segment values are escaped so they never contain the
+    /// delimiter. Returns `None` if a segment's field has no value.
+    pub fn render(&self, values: &HashMap<String, String>, delimiter: char) -> Option<String> {
+        let mut parts: Vec<Cow<'_, str>> = Vec::new();
+        parts.extend(self.prefix.as_deref().map(Cow::Borrowed));
+        for segment in &self.segments {
+            parts.extend(segment.prefix.as_deref().map(Cow::Borrowed));
+            let value = values.get(&segment.struct_field_name)?;
+            let encoded = segment.encoding.encode(value);
+            parts.push(Cow::Owned(escape_segment(&encoded, delimiter).into_owned()));
+        }
+        parts.extend(self.suffix.as_deref().map(Cow::Borrowed));
+        Some(parts.join(&delimiter.to_string()))
+    }
+
+    /// Renders the leading segments whose fields are in `values`, stopping at
+    /// the first one that is missing, for `begins_with` conditions. A partial
+    /// prefix ends with the delimiter, so `p#1#` does not match `p#10`. With
+    /// every field present this is the full value.
+    pub fn render_prefix(&self, values: &HashMap<String, String>, delimiter: char) -> String {
+        if let Some(full) = self.render(values, delimiter) {
+            return full;
+        }
+        let mut prefix = String::new();
+        let mut push = |part: &str| {
+            prefix.push_str(part);
+            prefix.push(delimiter);
+        };
+        if let Some(value_prefix) = &self.prefix {
+            push(value_prefix);
+        }
+        for segment in &self.segments {
+            let Some(value) = values.get(&segment.struct_field_name) else {
+                break;
+            };
+            if let Some(segment_prefix) = &segment.prefix {
+                push(segment_prefix);
+            }
+            push(&escape_segment(&segment.encoding.encode(value), delimiter));
+        }
+        prefix
+    }
+
+    /// Splits a stored value back into `(struct_field_name, raw value)` pairs,
+    /// matching the prefix, segment prefixes and suffix token by token and
+    /// unescaping each segment value.
+    pub fn parse(&self, value: &str, delimiter: char) -> Option<Vec<(String, String)>> {
+        let mut tokens = value.split(delimiter);
+        let expect = |tokens: &mut std::str::Split<'_, char>, literal: &Option<String>| {
+            literal.as_ref().is_none_or(|literal| {
+                literal
+                    .split(delimiter)
+                    .all(|part| tokens.next() == Some(part))
+            })
+        };
+
+        if !expect(&mut tokens, &self.prefix) {
+            return None;
+        }
+        let mut fields = Vec::with_capacity(self.segments.len());
+        for segment in &self.segments {
+            if !expect(&mut tokens, &segment.prefix) {
+                return None;
+            }
+            let raw = unescape_segment(tokens.next()?)?;
+            let decoded = segment.encoding.decode(&raw)?;
+            fields.push((segment.struct_field_name.clone(), decoded));
+        }
+        if !expect(&mut tokens, &self.suffix) {
+            return None;
+        }
+
+        match tokens.next() {
+            Some(_) => None,
+            None => Some(fields),
+        }
+    }
+
+    /// Whether the attribute may be absent, because one of its fields is an `Option`.
+    pub fn is_sparse(&self) -> bool {
+        self.segments.iter().any(|segment| segment.optional)
+    }
+
+    /// The struct field this value passes through unchanged, if it has a single
+    /// plain segment and no prefixes or suffix.
+    pub fn bare_field(&self) -> Option<&str> {
+        match &self.segments[..] {
+            [segment]
+                if self.prefix.is_none()
+                    && self.suffix.is_none()
+                    && segment.prefix.is_none()
+                    && segment.encoding == SegmentEncoding::Plain
+                    && segment.format.is_none() =>
+            {
+                Some(&segment.struct_field_name)
+            }
+            _ => None,
+        }
+    }
+
+    /// The type of the attribute: the field's own for a bare key, `S` otherwise.
+    pub fn scalar_type(&self) -> ScalarType {
+        match (self.bare_field(), &self.segments[..]) {
+            (Some(_), [segment]) => segment.scalar_type,
+            _ => ScalarType::S,
+        }
+    }
+}
+
+/// Percent-encodes the delimiter and [`ESCAPE`] itself, so a segment value
+/// never splits a composite key. Other characters are kept as they are.
+pub fn escape_segment(value: &str, delimiter: char) -> Cow<'_, str> {
+    if !value.contains([delimiter, ESCAPE]) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 4);
+    for c in value.chars() {
+        if c == delimiter || c == ESCAPE {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                escaped.push_str(&format!("{ESCAPE}{byte:02X}"));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Reverses [`escape_segment`]. Returns `None` for a malformed escape.
+pub fn unescape_segment(token: &str) -> Option<String> {
+    if !token.contains(ESCAPE) {
+        return Some(token.to_string());
+    }
+    let mut bytes = Vec::with_capacity(token.len());
+    let mut rest = token.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == ESCAPE as u8 {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `u#{user_id}#p#{post_id}`, both plain
+    fn user_post() -> CompositeAttributeValue {
+        let segment = |field: &str, prefix: &str| Segment {
+            struct_field_name: field.to_string(),
+            prefix: Some(prefix.to_string()),
+            encoding: SegmentEncoding::Plain,
+            format: None,
+            optional: false,
+            scalar_type: ScalarType::S,
+        };
+        CompositeAttributeValue {
+            segments: vec![segment("user_id", "u"), segment("post_id", "p")],
+            prefix: None,
+            suffix: None,
+        }
+    }
+
+    fn values(user_id: &str, post_id: &str) -> HashMap<String, String> {
+        HashMap::from([
+            ("user_id".to_string(), user_id.to_string()),
+            ("post_id".to_string(), post_id.to_string()),
+        ])
+    }
+
+    #[test]
+    fn escape_round_trips_the_delimiter_and_escape() {
+        for value in ["a#b", "100%", "%23", "#", "%", "ü#ö|%", ""] {
+            for delimiter in [DEFAULT_DELIMITER, '|'] {
+                let escaped = escape_segment(value, delimiter);
+                assert!(!escaped.contains(delimiter), "{escaped}");
+                assert_eq!(unescape_segment(&escaped).as_deref(), Some(value));
+            }
+        }
+        assert_eq!(escape_segment("a#b%c", '#'), "a%23b%25c");
+        assert_eq!(escape_segment("plain", '#'), "plain");
+    }
+
+    #[test]
+    fn unescape_rejects_malformed_escapes() {
+        assert_eq!(unescape_segment("%"), None);
+        assert_eq!(unescape_segment("%2"), None);
+        assert_eq!(unescape_segment("%zz"), None);
+        assert_eq!(unescape_segment("%FF"), None);
+    }
+
+    #[test]
+    fn values_with_the_delimiter_survive_render_and_parse() {
+        let composite = user_post();
+        let values = values("a#b", "50%#off");
+        let rendered = composite.render(&values, DEFAULT_DELIMITER).unwrap();
+        assert_eq!(rendered, "u#a%23b#p#50%25%23off");
+        let mut parsed = composite.parse(&rendered, DEFAULT_DELIMITER).unwrap();
+        parsed.sort();
+        assert_eq!(
+            parsed,
+            vec![
+                ("post_id".to_string(), "50%#off".to_string()),
+                ("user_id".to_string(), "a#b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_values_of_another_layout() {
+        let composite = user_post();
+        let parse = |value: &str| composite.parse(value, DEFAULT_DELIMITER);
+        assert!(parse("u#1#p#2").is_some());
+        // Wrong segment prefix, wrong first prefix, missing and extra segments
+        assert_eq!(parse("u#1#q#2"), None);
+        assert_eq!(parse("x#1#p#2"), None);
+        assert_eq!(parse("u#1#p"), None);
+        assert_eq!(parse("u#1#p#2#3"), None);
+        // Another delimiter does not split the value
+        assert_eq!(composite.parse("u|1|p|2", DEFAULT_DELIMITER), None);
+        assert_eq!(parse("u#1%2#p#2"), None);
+    }
+
+    #[test]
+    fn decoding_an_item_rejects_a_key_of_another_entity() {
+        let schema = SchemaV2 {
+            partition_key_def: KeyDef {
+                attribute_name: "pk".to_string(),
+                attribute_value: user_post(),
+            },
+            sort_key_def: None,
+            non_key_defs: vec![],
+            index_defs: vec![],
+            delimiter: '|',
+            version_attribute: None,
+            table_name: None,
+        };
+        let item = serde_json::json!({ "pk": "u|a#b%7C|p|2" });
+        let decoded = schema.decode_segments(&item).unwrap();
+        assert_eq!(decoded["user_id"], "a#b|");
+        assert_eq!(decoded["post_id"], "2");
+
+        let item = serde_json::json!({ "pk": "org|1|p|2" });
+        assert!(matches!(
+            schema.decode_segments(&item),
+            Err(crate::ItemError::MalformedAttribute { attribute, .. }) if attribute == "pk"
+        ));
+    }
+
+    #[test]
+    fn parse_checks_the_value_prefix_and_suffix() {
+        let composite = CompositeAttributeValue {
+            prefix: Some("post".to_string()),
+            suffix: Some("v1".to_string()),
+            ..user_post()
+        };
+        let rendered = composite
+            .render(&values("1", "2"), DEFAULT_DELIMITER)
+            .unwrap();
+        assert_eq!(rendered, "post#u#1#p#2#v1");
+        assert!(composite.parse(&rendered, DEFAULT_DELIMITER).is_some());
+        assert_eq!(composite.parse("user#u#1#p#2#v1", DEFAULT_DELIMITER), None);
+        assert_eq!(composite.parse("post#u#1#p#2#v2", DEFAULT_DELIMITER), None);
+        assert_eq!(composite.parse("u#1#p#2", DEFAULT_DELIMITER), None);
+    }
+
+    /// Encodes every value, checks each decodes back, and that the encoded
+    /// strings sort in the same order as `values`, which must be ascending.
+    fn assert_round_trip_sorted(encoding: SegmentEncoding, values: &[&str]) {
+        let encoded = values
+            .iter()
+            .map(|value| encoding.encode(value).into_owned())
+            .collect::<Vec<_>>();
+        for (value, token) in values.iter().zip(&encoded) {
+            assert_eq!(encoding.decode(token).as_deref(), Some(*value), "{token}");
+        }
+        let mut sorted = encoded.clone();
+        sorted.sort();
+        assert_eq!(sorted, encoded);
+    }
+
+    #[test]
+    fn pad_round_trips_and_sorts() {
+        assert_eq!(SegmentEncoding::Pad(5).encode("42"), "00042");
+        assert_round_trip_sorted(SegmentEncoding::Pad(5), &["0", "7", "42", "1000", "99999"]);
+    }
+
+    #[test]
+    fn pad_leaves_wider_values_as_they_are() {
+        assert_eq!(SegmentEncoding::Pad(2).encode("123"), "123");
+        assert_eq!(
+            SegmentEncoding::Pad(2).decode("123").as_deref(),
+            Some("123")
+        );
+    }
+
+    #[test]
+    fn sortable_int_round_trips_and_sorts() {
+        let values = [
+            i64::MIN.to_string(),
+            "-12".to_string(),
+            "-5".to_string(),
+            "0".to_string(),
+            "5".to_string(),
+            "12".to_string(),
+            i64::MAX.to_string(),
+            u64::MAX.to_string(),
+        ];
+        let values = values.iter().map(String::as_str).collect::<Vec<_>>();
+        assert_round_trip_sorted(SegmentEncoding::SortableInt, &values);
+    }
+
+    #[test]
+    fn sortable_float_round_trips_and_sorts() {
+        assert_round_trip_sorted(
+            SegmentEncoding::SortableFloat,
+            &["-inf", "-12.5", "-0.25", "0", "0.25", "3", "12.5", "inf"],
+        );
+    }
+}
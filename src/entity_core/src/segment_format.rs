@@ -0,0 +1,169 @@
+//! Key segments written through a strftime-style `format` rather than `Display`.
+//!
+//! Implementations for `chrono` and `time` types are behind the features of the
+//! same name. Both read the same `%Y-%m-%d` style format strings.
+
+/// A field type that can be written into a key segment with a `format` string
+/// and parsed back from it with the same string. Both fail on a format the
+/// type cannot be written or read with.
+pub trait FormattedSegment: Sized {
+    fn format_segment(&self, format: &str) -> Result<String, String>;
+    fn parse_segment(value: &str, format: &str) -> Result<Self, String>;
+}
+
+//
+// ─── CHRONO ─────────────────────────────────────────────────────────────────────
+//
+
+#[cfg(feature = "chrono")]
+fn chrono_format(value: impl std::fmt::Display, format: &str) -> Result<String, String> {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    write!(out, "{value}").map_err(|_| {
+        format!("{format:?} has an unknown specifier, or one the type has no value for")
+    })?;
+    Ok(out)
+}
+
+#[cfg(feature = "chrono")]
+impl FormattedSegment for chrono::NaiveDate {
+    fn format_segment(&self, format: &str) -> Result<String, String> {
+        chrono_format(self.format(format), format)
+    }
+
+    fn parse_segment(value: &str, format: &str) -> Result<Self, String> {
+        Self::parse_from_str(value, format).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl FormattedSegment for chrono::NaiveDateTime {
+    fn format_segment(&self, format: &str) -> Result<String, String> {
+        chrono_format(self.format(format), format)
+    }
+
+    fn parse_segment(value: &str, format: &str) -> Result<Self, String> {
+        Self::parse_from_str(value, format).map_err(|err| err.to_string())
+    }
+}
+
+/// Formats without an offset (`%z`) are read back as UTC.
+#[cfg(feature = "chrono")]
+impl FormattedSegment for chrono::DateTime<chrono::Utc> {
+    fn format_segment(&self, format: &str) -> Result<String, String> {
+        chrono_format(self.format(format), format)
+    }
+
+    fn parse_segment(value: &str, format: &str) -> Result<Self, String> {
+        match chrono::DateTime::parse_from_str(value, format) {
+            Ok(datetime) => Ok(datetime.to_utc()),
+            Err(_) => chrono::NaiveDateTime::parse_from_str(value, format)
+                .map(|datetime| datetime.and_utc())
+                .map_err(|err| err.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl FormattedSegment for chrono::DateTime<chrono::FixedOffset> {
+    fn format_segment(&self, format: &str) -> Result<String, String> {
+        chrono_format(self.format(format), format)
+    }
+
+    fn parse_segment(value: &str, format: &str) -> Result<Self, String> {
+        Self::parse_from_str(value, format).map_err(|err| err.to_string())
+    }
+}
+
+//
+// ─── TIME ───────────────────────────────────────────────────────────────────────
+//
+
+#[cfg(feature = "time")]
+fn time_format_items(
+    format: &str,
+) -> Result<Vec<time::format_description::BorrowedFormatItem<'_>>, String> {
+    time::format_description::parse_strftime_borrowed(format).map_err(|err| err.to_string())
+}
+
+#[cfg(feature = "time")]
+macro_rules! impl_time_format_segment {
+    () => {
+        fn format_segment(&self, format: &str) -> Result<String, String> {
+            self.format(&time_format_items(format)?)
+                .map_err(|err| err.to_string())
+        }
+    };
+}
+
+#[cfg(feature = "time")]
+impl FormattedSegment for time::Date {
+    impl_time_format_segment!();
+
+    fn parse_segment(value: &str, format: &str) -> Result<Self, String> {
+        Self::parse(value, &time_format_items(format)?).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(feature = "time")]
+impl FormattedSegment for time::PrimitiveDateTime {
+    impl_time_format_segment!();
+
+    fn parse_segment(value: &str, format: &str) -> Result<Self, String> {
+        Self::parse(value, &time_format_items(format)?).map_err(|err| err.to_string())
+    }
+}
+
+/// Formats without an offset (`%z`) are read back as UTC.
+#[cfg(feature = "time")]
+impl FormattedSegment for time::OffsetDateTime {
+    impl_time_format_segment!();
+
+    fn parse_segment(value: &str, format: &str) -> Result<Self, String> {
+        let items = time_format_items(format)?;
+        match Self::parse(value, &items) {
+            Ok(datetime) => Ok(datetime),
+            Err(_) => time::PrimitiveDateTime::parse(value, &items)
+                .map(|datetime| datetime.assume_utc())
+                .map_err(|err| err.to_string()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "chrono", feature = "time"))]
+mod tests {
+    use super::FormattedSegment;
+    use crate::{ItemError, formatted_segment};
+
+    #[test]
+    fn a_bad_format_is_an_error_rather_than_a_panic() {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 3, 9).unwrap();
+        assert_eq!(date.format_segment("%Y-%m-%d").unwrap(), "2024-03-09");
+        assert!(date.format_segment("%H:%M").is_err());
+        assert!(matches!(
+            formatted_segment(&date, "due_on", "%Q"),
+            Err(ItemError::InvalidField { field, .. }) if field == "due_on"
+        ));
+
+        let date = time::Date::from_calendar_date(2024, time::Month::March, 9).unwrap();
+        assert_eq!(date.format_segment("%Y-%m-%d").unwrap(), "2024-03-09");
+        assert!(date.format_segment("%H:%M").is_err());
+        assert!(date.format_segment("%Q").is_err());
+    }
+
+    #[test]
+    fn formats_parse_back() {
+        let datetime = chrono::NaiveDate::from_ymd_opt(2024, 3, 9)
+            .unwrap()
+            .and_hms_opt(13, 5, 7)
+            .unwrap()
+            .and_utc();
+        let format = "%Y-%m-%dT%H:%M:%S";
+        let written = datetime.format_segment(format).unwrap();
+        assert_eq!(
+            chrono::DateTime::<chrono::Utc>::parse_segment(&written, format).unwrap(),
+            datetime
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! Which table each entity is stored in.
+//!
+//! An entity names its table with `#[dynodmize(table = "...")]`. A
+//! [`TableConfig`] resolves that name at runtime, so the same entities can be
+//! pointed at `dev-` and `prod-` tables or at several tables in one process.
+
+use crate::{Entity2, Error};
+use std::collections::HashMap;
+
+/// Resolves the table of one entity type against a config that may only be
+/// given later, as batches do.
+pub(crate) type Resolve = fn(&TableConfig) -> Result<String, Error>;
+
+/// How entities map to table names at runtime.
+///
+/// An entity's table is its override in the config if there is one, otherwise
+/// the table it declares, otherwise the config's default table. The prefix is
+/// put in front of whichever applies.
+#[derive(Debug, Clone, Default)]
+pub struct TableConfig {
+    prefix: String,
+    default_table: Option<String>,
+    /// By the entity's type name
+    overrides: HashMap<&'static str, String>,
+}
+
+impl TableConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put in front of every table name, such as `dev-`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// The table of entities that do not declare one.
+    pub fn default_table(mut self, table: impl Into<String>) -> Self {
+        self.default_table = Some(table.into());
+        self
+    }
+
+    /// Stores `T` in `table` instead of the table it declares.
+    pub fn table<T: Entity2>(mut self, table: impl Into<String>) -> Self {
+        self.overrides
+            .insert(std::any::type_name::<T>(), table.into());
+        self
+    }
+
+    /// The full name of the table `T` is stored in.
+    pub fn resolve<T: Entity2>(&self) -> Result<String, Error> {
+        let type_name = std::any::type_name::<T>();
+        let table = match self.overrides.get(type_name) {
+            Some(table) => table.clone(),
+            None => T::get_schema()
+                .table_name
+                .or_else(|| self.default_table.clone())
+                .ok_or_else(|| {
+                    Error::InvalidRequest(format!(
+                        "`{type_name}` has no table, declare one with \
+                         #[dynodmize(table = \"...\")] or set a default table"
+                    ))
+                })?,
+        };
+        Ok(format!("{}{table}", self.prefix))
+    }
+}
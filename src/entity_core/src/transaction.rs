@@ -0,0 +1,320 @@
+//! Atomic writes across items and entities with `TransactWriteItems`.
+//!
+//! Operations are the same builders as single writes, so conditions and
+//! versions apply the same way. Either every operation goes through or none do.
+
+use crate::{
+    Condition, CreateBuilder, DeleteBuilder, Entity2, Error, ExpectedVersion, PrimaryKey, Resolve,
+    TableConfig, UpdateBuilder, Write, non_empty,
+};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::{ConditionCheck, Delete, Put, TransactWriteItem, Update};
+
+/// The most operations DynamoDB accepts in one transaction.
+pub const MAX_TRANSACTION_OPERATIONS: usize = 100;
+
+/// One action of the transaction, with the version it expects if it is a
+/// versioned write.
+struct Operation {
+    item: TransactWriteItem,
+    version: Option<ExpectedVersion>,
+}
+
+/// An operation as it is added, before the transaction's own tables are known.
+enum Pending {
+    Ready(Box<Operation>),
+    /// Goes to the table `resolve` gives for the transaction's [`TableConfig`]
+    ConditionCheck {
+        resolve: Resolve,
+        check: Box<Write>,
+    },
+}
+
+impl Pending {
+    fn resolve(self, tables: &TableConfig) -> Result<Operation, Error> {
+        let (resolve, check) = match self {
+            Pending::Ready(operation) => return Ok(*operation),
+            Pending::ConditionCheck { resolve, check } => (resolve, check),
+        };
+        let check = ConditionCheck::builder()
+            .table_name(resolve(tables)?)
+            .set_key(Some(check.item))
+            .set_condition_expression(check.condition)
+            .set_expression_attribute_names(non_empty(check.names))
+            .set_expression_attribute_values(non_empty(check.values))
+            .build()
+            .expect("table name, key and condition are set");
+        Ok(Operation {
+            item: TransactWriteItem::builder().condition_check(check).build(),
+            version: None,
+        })
+    }
+}
+
+/// Puts, updates, deletes and condition checks on items of any entities,
+/// applied all together or not at all.
+///
+/// A failed condition comes back as [`Error::TransactionCanceled`], naming the
+/// operation by its position in the order the operations were added.
+///
+/// Puts, updates and deletes go to the tables of their builders, which may
+/// differ. Condition checks go to the transaction's own [`TableConfig`],
+/// whether it is set before or after them.
+pub struct Transaction {
+    client: Client,
+    operations: Vec<Result<Pending, Error>>,
+    tables: TableConfig,
+}
+
+impl Transaction {
+    pub fn new(client: Client) -> Self {
+        Transaction {
+            client,
+            operations: vec![],
+            tables: TableConfig::default(),
+        }
+    }
+
+    /// Resolves the tables of all condition checks through `tables`.
+    pub fn tables(mut self, tables: TableConfig) -> Self {
+        self.tables = tables;
+        self
+    }
+
+    /// Puts the entity of `create`, with its condition and version.
+    pub fn put<T: Entity2>(mut self, create: CreateBuilder<T>) -> Self {
+        let operation = create.tables.resolve::<T>().and_then(|table| {
+            let write = create.prepare()?;
+            let return_old = write.return_old();
+            let put = Put::builder()
+                .table_name(table)
+                .set_item(Some(write.item))
+                .set_condition_expression(write.condition)
+                .set_expression_attribute_names(non_empty(write.names))
+                .set_expression_attribute_values(non_empty(write.values))
+                .set_return_values_on_condition_check_failure(return_old)
+                .build()
+                .expect("table name and item are set");
+            Ok(Operation {
+                item: TransactWriteItem::builder().put(put).build(),
+                version: write.version,
+            })
+        });
+        self.operations
+            .push(operation.map(|operation| Pending::Ready(Box::new(operation))));
+        self
+    }
+
+    /// Applies an update, which must keep the item's key. Derived attributes
+    /// are rewritten as for a single update.
+    pub fn update<T: Entity2>(mut self, update: impl Into<UpdateBuilder<T>>) -> Self {
+        let update = update.into();
+        let operation = update.tables.resolve::<T>().and_then(|table| {
+            let write = update.prepare_in_transaction()?;
+            let return_old = write.return_old();
+            let update = Update::builder()
+                .table_name(table)
+                .set_key(Some(write.item))
+                .set_update_expression(write.update_expression)
+                .set_condition_expression(write.condition)
+                .set_expression_attribute_names(non_empty(write.names))
+                .set_expression_attribute_values(non_empty(write.values))
+                .set_return_values_on_condition_check_failure(return_old)
+                .build()
+                .expect("table name, key and update expression are set");
+            Ok(Operation {
+                item: TransactWriteItem::builder().update(update).build(),
+                version: write.version,
+            })
+        });
+        self.operations
+            .push(operation.map(|operation| Pending::Ready(Box::new(operation))));
+        self
+    }
+
+    pub fn delete<K: PrimaryKey>(mut self, delete: DeleteBuilder<K>) -> Self {
+        let operation = delete.tables.resolve::<K::Entity>().and_then(|table| {
+            let Write {
+                item,
+                condition,
+                names,
+                values,
+                ..
+            } = delete.prepare()?;
+            let delete = Delete::builder()
+                .table_name(table)
+                .set_key(Some(item))
+                .set_condition_expression(condition)
+                .set_expression_attribute_names(non_empty(names))
+                .set_expression_attribute_values(non_empty(values))
+                .build()
+                .expect("table name and key are set");
+            Ok(Operation {
+                item: TransactWriteItem::builder().delete(delete).build(),
+                version: None,
+            })
+        });
+        self.operations
+            .push(operation.map(|operation| Pending::Ready(Box::new(operation))));
+        self
+    }
+
+    /// Cancels the transaction unless `condition` holds for the item under
+    /// `key`, without writing that item.
+    pub fn condition_check<K: PrimaryKey>(
+        mut self,
+        key: K,
+        condition: Condition<K::Entity>,
+    ) -> Self {
+        let operation = key.to_key().map_err(Error::from).map(|key| {
+            let check = Write::new(key).with_condition(Some(&condition), &K::Entity::get_schema());
+            Pending::ConditionCheck {
+                resolve: TableConfig::resolve::<K::Entity>,
+                check: Box::new(check),
+            }
+        });
+        self.operations.push(operation);
+        self
+    }
+
+    /// The operations in the order they were added, with every table resolved.
+    fn resolved(self) -> Result<(Client, Vec<Operation>), Error> {
+        let operations = self
+            .operations
+            .into_iter()
+            .map(|operation| operation?.resolve(&self.tables))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((self.client, operations))
+    }
+
+    pub async fn send(self) -> Result<(), Error> {
+        let (client, operations) = self.resolved()?;
+        if operations.is_empty() || operations.len() > MAX_TRANSACTION_OPERATIONS {
+            return Err(Error::InvalidRequest(format!(
+                "a transaction takes 1 to {MAX_TRANSACTION_OPERATIONS} operations, not {}",
+                operations.len()
+            )));
+        }
+        let (items, versions): (Vec<_>, Vec<_>) = operations
+            .into_iter()
+            .map(|operation| (operation.item, operation.version))
+            .unzip();
+        client
+            .transact_write_items()
+            .set_transact_items(Some(items))
+            .send()
+            .await
+            .map_err(|err| Error::from_transaction(err, &versions))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CompositeAttributeValue, DEFAULT_DELIMITER, Item, ItemError, KeyDef, ScalarType, SchemaV2,
+        Segment, SegmentEncoding, attribute_field_value, segment_value, serialize_attributes,
+    };
+    use aws_sdk_dynamodb::types::AttributeValue as Av;
+    use std::collections::HashMap;
+
+    /// An entity under `a#{id}` that declares no table
+    #[derive(serde::Serialize)]
+    struct Account {
+        id: u32,
+    }
+
+    struct AccountKey {
+        id: u32,
+    }
+
+    impl Entity2 for Account {
+        fn get_schema() -> SchemaV2 {
+            SchemaV2 {
+                partition_key_def: KeyDef {
+                    attribute_name: "pk".to_string(),
+                    attribute_value: CompositeAttributeValue {
+                        segments: vec![Segment {
+                            struct_field_name: "id".to_string(),
+                            prefix: Some("a".to_string()),
+                            encoding: SegmentEncoding::Plain,
+                            format: None,
+                            optional: false,
+                            scalar_type: ScalarType::S,
+                        }],
+                        prefix: None,
+                        suffix: None,
+                    },
+                },
+                sort_key_def: None,
+                non_key_defs: vec![],
+                index_defs: vec![],
+                delimiter: DEFAULT_DELIMITER,
+                version_attribute: None,
+                table_name: None,
+            }
+        }
+
+        fn segment_values(&self) -> Result<HashMap<String, String>, ItemError> {
+            Ok(HashMap::from([("id".to_string(), self.id.to_string())]))
+        }
+
+        fn segment_value(field: &str, value: &Av) -> Result<String, ItemError> {
+            Ok(attribute_field_value::<u32>(value, field)?.to_string())
+        }
+
+        fn field_attributes(&self) -> Item {
+            serialize_attributes(self)
+        }
+
+        fn from_item(item: &serde_json::Value) -> Result<Self, ItemError> {
+            let segments = Self::get_schema().decode_segments(item)?;
+            Ok(Account {
+                id: segment_value(&segments, "id")?,
+            })
+        }
+    }
+
+    impl PrimaryKey for AccountKey {
+        type Entity = Account;
+
+        fn segment_values(&self) -> Result<HashMap<String, String>, ItemError> {
+            Ok(HashMap::from([("id".to_string(), self.id.to_string())]))
+        }
+
+        fn field_attributes(&self) -> Item {
+            Item::from([("id".to_string(), Av::N(self.id.to_string()))])
+        }
+    }
+
+    fn client() -> Client {
+        Client::from_conf(
+            aws_sdk_dynamodb::Config::builder()
+                .behavior_version(aws_sdk_dynamodb::config::BehaviorVersion::latest())
+                .build(),
+        )
+    }
+
+    #[test]
+    fn condition_checks_use_the_tables_set_after_them() {
+        let (_, operations) = Transaction::new(client())
+            .condition_check(AccountKey { id: 1 }, Condition::item_exists())
+            .tables(TableConfig::new().prefix("dev-").default_table("accounts"))
+            .resolved()
+            .unwrap();
+        let check = operations[0].item.condition_check().unwrap();
+        assert_eq!(check.table_name(), "dev-accounts");
+        assert_eq!(check.key()["pk"], Av::S("a#1".to_string()));
+    }
+
+    #[test]
+    fn a_condition_check_without_a_table_fails_the_transaction() {
+        let transaction = Transaction::new(client())
+            .condition_check(AccountKey { id: 1 }, Condition::item_exists());
+        assert!(matches!(
+            transaction.resolved(),
+            Err(Error::InvalidRequest(_))
+        ));
+    }
+}
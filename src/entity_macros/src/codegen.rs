@@ -1,9 +1,12 @@
-use entity_core::{AttributeValue, CompositeAttributeValue, SchemaV2, Segment};
+use crate::parser::{EnumEntity, option_inner};
+use entity_core::{
+    AttributeValue, CompositeAttributeValue, IndexKind, KeyDef, ScalarType, SchemaV2, Segment,
+    SegmentEncoding,
+};
 use proc_macro2::TokenStream;
-use quote::quote;
-use syn::DeriveInput;
-
-pub const DELIMITER: char = '#';
+use quote::{format_ident, quote};
+use std::collections::HashMap;
+use syn::{Data, DeriveInput};
 
 pub fn tok_optional_string(v: &Option<String>) -> TokenStream {
     match v {
@@ -40,43 +43,110 @@ pub fn tok_key_def(attribute_name: &str, attribute_value: &AttributeValue) -> To
     }
 }
 
+pub fn tok_composite_key_def(
+    attribute_name: &str,
+    attribute_value: &CompositeAttributeValue,
+) -> TokenStream {
+    let vp = tok_optional_string(&attribute_value.prefix);
+    let vs = tok_optional_string(&attribute_value.suffix);
+    let segments = tok_segments(&attribute_value.segments);
+    quote! {
+        entity_core::KeyDef {
+            attribute_name: #attribute_name.to_string(),
+            attribute_value: entity_core::CompositeAttributeValue {
+                segments: #segments,
+                prefix: #vp,
+                suffix: #vs,
+            },
+        }
+    }
+}
+
 pub(crate) fn tok_segments(segments: &[Segment]) -> TokenStream {
     let parts = segments.iter().map(|segment| {
         let field = &segment.struct_field_name;
-        match &segment.prefix {
-            Some(p) => quote! {
-                entity_core::Segment {
-                    struct_field_name: #field.to_string(),
-                    prefix: Some(#p.to_string()),
-                }
-            },
-            None => quote! {
-                entity_core::Segment {
-                    struct_field_name: #field.to_string(),
-                    prefix: None,
-                }
-            },
+        let prefix = tok_optional_string(&segment.prefix);
+        let encoding = tok_segment_encoding(segment.encoding);
+        let format = tok_optional_string(&segment.format);
+        let optional = segment.optional;
+        let scalar_type = match segment.scalar_type {
+            ScalarType::S => quote! { entity_core::ScalarType::S },
+            ScalarType::N => quote! { entity_core::ScalarType::N },
+            ScalarType::B => quote! { entity_core::ScalarType::B },
+        };
+        quote! {
+            entity_core::Segment {
+                struct_field_name: #field.to_string(),
+                prefix: #prefix,
+                encoding: #encoding,
+                format: #format,
+                optional: #optional,
+                scalar_type: #scalar_type,
+            }
         }
     });
     quote! { Vec::<entity_core::Segment>::from([ #( #parts ),* ]) }
 }
 
-pub fn generate_impl(input: &DeriveInput, schema: SchemaV2) -> TokenStream {
-    // --- PK tokens ---
-    let pk_attr_name = schema.partition_key_def.attribute_name;
-    let pk_vp = tok_optional_string(&schema.partition_key_def.attribute_value.prefix);
-    let pk_vs = tok_optional_string(&schema.partition_key_def.attribute_value.suffix);
-    let pk_segments = tok_segments(&schema.partition_key_def.attribute_value.segments);
-    let partition_key_def_tokens = quote! {
-            entity_core::KeyDef {
-                attribute_name: #pk_attr_name.to_string(),
-                attribute_value: entity_core::CompositeAttributeValue {
-                    segments: #pk_segments,
-                    prefix: #pk_vp,
-                    suffix: #pk_vs,
-                },
-            }
+fn tok_segment_encoding(encoding: SegmentEncoding) -> TokenStream {
+    match encoding {
+        SegmentEncoding::Plain => quote! { entity_core::SegmentEncoding::Plain },
+        SegmentEncoding::Pad(width) => quote! { entity_core::SegmentEncoding::Pad(#width) },
+        SegmentEncoding::SortableInt => quote! { entity_core::SegmentEncoding::SortableInt },
+        SegmentEncoding::SortableFloat => quote! { entity_core::SegmentEncoding::SortableFloat },
+    }
+}
+
+/// `Vec<u8>` fields are stored as binary attributes.
+pub(crate) fn is_bytes(ty: &syn::Type) -> bool {
+    let syn::Type::Path(type_path) = ty else {
+        return false;
+    };
+    let Some(last) = type_path.path.segments.last() else {
+        return false;
     };
+    if last.ident != "Vec" {
+        return false;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &last.arguments else {
+        return false;
+    };
+    matches!(
+        args.args.first(),
+        Some(syn::GenericArgument::Type(syn::Type::Path(inner))) if inner.path.is_ident("u8")
+    )
+}
+
+/// Primitive integer fields, which can be counted with `ADD`.
+pub(crate) fn is_integer(ty: &syn::Type) -> bool {
+    let syn::Type::Path(type_path) = ty else {
+        return false;
+    };
+    type_path.path.get_ident().is_some_and(|ident| {
+        matches!(
+            ident.to_string().as_str(),
+            "i8" | "i16"
+                | "i32"
+                | "i64"
+                | "i128"
+                | "isize"
+                | "u8"
+                | "u16"
+                | "u32"
+                | "u64"
+                | "u128"
+                | "usize"
+        )
+    })
+}
+
+/// The `SchemaV2` expression returned by `get_schema`
+fn tok_schema(schema: &SchemaV2) -> TokenStream {
+    // --- PK tokens ---
+    let partition_key_def_tokens = tok_composite_key_def(
+        &schema.partition_key_def.attribute_name,
+        &schema.partition_key_def.attribute_value,
+    );
 
     // --- SK tokens (optional) ---
     let sort_key_def_tokens = if let Some(sk_def) = &schema.sort_key_def {
@@ -96,181 +166,727 @@ pub fn generate_impl(input: &DeriveInput, schema: SchemaV2) -> TokenStream {
         quote! { Vec::<entity_core::KeyDef<entity_core::AttributeValue>>::from([ #( #items ),* ]) }
     };
 
+    // --- Index tokens ---
+    let index_items = {
+        let items = schema.index_defs.iter().map(|index| {
+            let index_name = &index.index_name;
+            let kind = match index.kind {
+                IndexKind::Global => quote! { entity_core::IndexKind::Global },
+                IndexKind::Local => quote! { entity_core::IndexKind::Local },
+            };
+            let pk = tok_composite_key_def(
+                &index.partition_key_def.attribute_name,
+                &index.partition_key_def.attribute_value,
+            );
+            let sk = match &index.sort_key_def {
+                Some(sk_def) => {
+                    let key_def = tok_key_def(&sk_def.attribute_name, &sk_def.attribute_value);
+                    quote! { Some(#key_def) }
+                }
+                None => quote! { None },
+            };
+            quote! {
+                entity_core::IndexDef {
+                    index_name: #index_name.to_string(),
+                    kind: #kind,
+                    partition_key_def: #pk,
+                    sort_key_def: #sk,
+                }
+            }
+        });
+        quote! { Vec::<entity_core::IndexDef>::from([ #( #items ),* ]) }
+    };
+
+    let delimiter = schema.delimiter;
+    let version_attribute = match &schema.version_attribute {
+        Some(attribute) => quote! { Some(#attribute.to_string()) },
+        None => quote! { None },
+    };
+    let table_name = match &schema.table_name {
+        Some(table) => quote! { Some(#table.to_string()) },
+        None => quote! { None },
+    };
+
+    quote! {
+        {
+            let partition_key_def = #partition_key_def_tokens;
+            let sort_key_def = #sort_key_def_tokens;
+            let non_key_defs: Vec<entity_core::KeyDef<entity_core::AttributeValue>> = #nk_items;
+            let index_defs: Vec<entity_core::IndexDef> = #index_items;
+
+            entity_core::SchemaV2 {
+                partition_key_def,
+                sort_key_def,
+                non_key_defs,
+                index_defs,
+                delimiter: #delimiter,
+                version_attribute: #version_attribute,
+                table_name: #table_name,
+            }
+        }
+    }
+}
+
+/// Every field that feeds a derived attribute. A field's `format` and
+/// optionality are the same in every key it feeds.
+fn segment_fields(schema: &SchemaV2) -> HashMap<&str, &Segment> {
+    schema
+        .composite_defs()
+        .flat_map(|(_, composite)| &composite.segments)
+        .map(|segment| (segment.struct_field_name.as_str(), segment))
+        .collect()
+}
+
+//
+// ─── SEGMENT VALUES ──────────────────────────────────
+//
+/// Statements inserting the string form of each segment field into `values`.
+/// `access` turns a field into an expression that borrows it.
+fn tok_value_inserts(
+    schema: &SchemaV2,
+    access: impl Fn(&syn::Ident) -> TokenStream,
+) -> Vec<TokenStream> {
+    let segment_fields = segment_fields(schema);
+    let mut segment_field_names: Vec<&str> = segment_fields.keys().copied().collect();
+    segment_field_names.sort();
+    segment_field_names
+        .iter()
+        .map(|field| {
+            let ident = syn::Ident::new(field, proc_macro2::Span::call_site());
+            let field_ref = access(&ident);
+            let segment = segment_fields[field];
+            // `value` is the `Some` of an optional field
+            let value = if segment.optional {
+                quote! { value }
+            } else {
+                field_ref.clone()
+            };
+            let rendered = tok_render_segment(segment, &value);
+            if segment.optional {
+                // `None` leaves the field out, which drops the attributes it feeds
+                quote! {
+                    if let Some(value) = #field_ref {
+                        values.insert(#field.to_string(), #rendered);
+                    }
+                }
+            } else {
+                quote! {
+                    values.insert(#field.to_string(), #rendered);
+                }
+            }
+        })
+        .collect()
+}
+
+/// The string form of a segment field, given an expression borrowing it. A
+/// formatted field may fail to render, which `?` hands to the caller as an
+/// `ItemError`.
+fn tok_render_segment(segment: &Segment, value: &TokenStream) -> TokenStream {
+    let field = &segment.struct_field_name;
+    match &segment.format {
+        Some(format) => {
+            quote! { entity_core::formatted_segment(#value, #field, #format)? }
+        }
+        None => quote! { #value.to_string() },
+    }
+}
+
+/// Match arms rendering one segment field from its typed attribute `value`.
+fn tok_segment_value_arms(fields: &syn::Fields, schema: &SchemaV2) -> Vec<TokenStream> {
+    let segment_fields = segment_fields(schema);
+    fields
+        .iter()
+        .filter_map(|field| {
+            let ident = field.ident.as_ref()?;
+            let field_name = ident.to_string();
+            let segment = segment_fields.get(field_name.as_str())?;
+            // An update never sets an optional field to `None`, it removes it
+            let ty = match segment.optional {
+                true => option_inner(&field.ty).unwrap_or(&field.ty),
+                false => &field.ty,
+            };
+            let rendered = tok_render_segment(segment, &quote! { (&value) });
+            Some(quote! {
+                #field_name => {
+                    let value: #ty = entity_core::attribute_field_value(value, #field_name)?;
+                    Ok(#rendered)
+                }
+            })
+        })
+        .collect()
+}
+
+//
+// ─── FROM ITEM ───────────────────────────────────────
+//
+/// Field initializers reading each field from `item`, or from the decoded
+/// `segments` for fields that feed a key.
+fn tok_field_inits(fields: &syn::Fields, schema: &SchemaV2) -> Vec<TokenStream> {
+    let segment_fields = segment_fields(schema);
+    fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .map(|ident| {
+            let field = ident.to_string();
+            let Some(segment) = segment_fields.get(field.as_str()) else {
+                return quote! { #ident: entity_core::field_value(item, #field)? };
+            };
+            // Fields that feed a key are read back from the composite value
+            let value = match &segment.format {
+                Some(format) => {
+                    quote! { entity_core::formatted_segment_value(&segments, #field, #format) }
+                }
+                None => quote! { entity_core::segment_value(&segments, #field) },
+            };
+            if segment.optional {
+                quote! { #ident: entity_core::optional_segment(#value)? }
+            } else {
+                quote! { #ident: #value? }
+            }
+        })
+        .collect()
+}
+
+pub fn generate_impl(input: &DeriveInput, schema: SchemaV2) -> TokenStream {
     let name = &input.ident;
+    let schema_tokens = tok_schema(&schema);
+    let value_inserts = tok_value_inserts(&schema, |ident| quote! { (&self.#ident) });
+    let field_inits = match &input.data {
+        Data::Struct(data_struct) => tok_field_inits(&data_struct.fields, &schema),
+        _ => vec![],
+    };
+    let segment_value_arms = match &input.data {
+        Data::Struct(data_struct) => tok_segment_value_arms(&data_struct.fields, &schema),
+        _ => vec![],
+    };
 
     //
-    // ─── PK ──────────────────────────────────────────────
+    // ─── ATTRIBUTE MAP ───────────────────────────────────
     //
-    let pk_expr = {
-        let segs = schema
-            .partition_key_def
-            .attribute_value
-            .segments
+    let binary_inserts = match &input.data {
+        Data::Struct(data_struct) => data_struct
+            .fields
             .iter()
-            .map(|segment| {
-                let field = syn::Ident::new(&segment.struct_field_name, proc_macro2::Span::call_site());
-                if let Some(pfx) = &segment.prefix {
-                    quote! { format!("{}#{}", #pfx, self.#field) }
-                } else {
-                    quote! { self.#field.to_string() }
+            .filter(|field| is_bytes(&field.ty))
+            .filter_map(|field| field.ident.as_ref())
+            .map(|ident| {
+                let field = ident.to_string();
+                quote! {
+                    item.insert(#field.to_string(), entity_core::binary_attribute(&self.#ident));
                 }
-            });
-        quote! { Vec::<String>::from([ #( #segs ),* ]).join("#") }
+            })
+            .collect::<Vec<_>>(),
+        _ => vec![],
     };
 
-    //
-    // ─── SK ──────────────────────────────────────────────
-    //
-    let sk_expr = if let Some(sk) = &schema.sort_key_def {
-        let sk_name = &sk.attribute_name;
+    let key_struct = match &input.data {
+        Data::Struct(data_struct) => generate_key_struct(input, &data_struct.fields, &schema),
+        _ => quote! {},
+    };
+    let sk_prefix = match &input.data {
+        Data::Struct(data_struct) => generate_sk_prefix(input, &data_struct.fields, &schema),
+        _ => quote! {},
+    };
+    let counters = match &input.data {
+        Data::Struct(data_struct) => generate_counters(input, &data_struct.fields, &schema),
+        _ => quote! {},
+    };
+    let condition_fields = match &input.data {
+        Data::Struct(data_struct) => generate_fields(input, &data_struct.fields, &schema),
+        _ => quote! {},
+    };
 
-        match &sk.attribute_value {
-            // If the sort key is static, directly insert it with the attribute name
-            AttributeValue::Static(static_value) => {
-                quote! {
-                {
-                    let mut map: ::std::collections::HashMap<String, String> = ::std::collections::HashMap::new();
-                    map.insert(#sk_name.to_string(), #static_value.to_string());
-                    map
+    // --- final impl ---
+    quote! {
+        #key_struct
+        #sk_prefix
+        #counters
+        #condition_fields
+
+        impl entity_core::Entity2 for #name {
+            fn get_schema() -> entity_core::SchemaV2 {
+                #schema_tokens
+            }
+
+            fn segment_values(
+                &self,
+            ) -> Result<::std::collections::HashMap<String, String>, entity_core::ItemError> {
+                let mut values = ::std::collections::HashMap::new();
+                #( #value_inserts )*
+                Ok(values)
+            }
+
+            fn segment_value(
+                field: &str,
+                value: &entity_core::__private::aws_sdk_dynamodb::types::AttributeValue,
+            ) -> Result<String, entity_core::ItemError> {
+                match field {
+                    #( #segment_value_arms )*
+                    _ => Err(entity_core::ItemError::InvalidField {
+                        field: field.to_string(),
+                        message: "does not feed a derived attribute".to_string(),
+                    }),
                 }
             }
+
+            fn field_attributes(&self) -> entity_core::Item {
+                let mut item = entity_core::serialize_attributes(self);
+                #( #binary_inserts )*
+                item
             }
-            // For composite sort keys, compute the value based on segments
-            AttributeValue::Composite(CompositeAttributeValue {
-                                          segments,
-                                          prefix,
-                                          suffix,
-                                      }) => {
-                // Generate the parts of the composite key based on the segments
-                let segment_parts = segments.iter().map(|segment| {
-                    let field = syn::Ident::new(&segment.struct_field_name, proc_macro2::Span::call_site());
-                    if let Some(pfx) = &segment.prefix {
-                        // Prefix is included if present
-                        quote! { format!("{}#{}", #pfx, self.#field) }
-                    } else {
-                        // Use the field value directly if no prefix
-                        quote! { self.#field.to_string() }
-                    }
-                });
 
-                // Handle the optional prefix and suffix for the composite key
-                let final_prefix = match prefix {
-                    Some(p) => quote! { parts.push(#p.to_string()); },
-                    None => quote! {},
-                };
-                let final_suffix = match suffix {
-                    Some(s) => quote! { parts.push(#s.to_string()); },
-                    None => quote! {},
-                };
+            /// Deserialize from `serde_json::Value`, splitting composite keys back into fields
+            fn from_item(
+                item: &entity_core::__private::serde_json::Value,
+            ) -> Result<Self, entity_core::ItemError> {
+                let segments = Self::get_schema().decode_segments(item)?;
 
-                quote! {
-                {
-                    let mut map: ::std::collections::HashMap<String, String> = ::std::collections::HashMap::new();
-
-                    // Collect all parts of the composite sort key
-                    let mut parts: Vec<String> = Vec::new();
-                    #final_prefix
-                    parts.extend(vec![
-                        #(#segment_parts),*
-                    ]);
-                    #final_suffix
-
-                    // Join the parts with "#" and insert into the map
-                    let composite_sk = parts.join("#");
-                    map.insert(#sk_name.to_string(), composite_sk);
-                    map
-                }
+                Ok(Self {
+                    #( #field_inits ),*
+                })
             }
+        }
+    }
+}
+
+//
+// ─── COUNTERS ────────────────────────────────────────
+//
+/// `<Entity>Counters`, with `increment_<field>`, `decrement_<field>` and
+/// `send_returning_<field>` for every integer field that no derived attribute
+/// is built from. A bare key
+/// named after its field, like `#[nk] followers`, is the counter itself.
+/// The `#[version]` field is left out, as only writes change it.
+fn generate_counters(input: &DeriveInput, fields: &syn::Fields, schema: &SchemaV2) -> TokenStream {
+    let name = &input.ident;
+    let vis = &input.vis;
+    let counters: Vec<(&syn::Ident, &syn::Type)> = fields
+        .iter()
+        .filter(|field| is_integer(&field.ty))
+        .filter_map(|field| Some((field.ident.as_ref()?, &field.ty)))
+        .filter(|(ident, _)| {
+            let field = ident.to_string();
+            if schema.version_attribute.as_deref() == Some(&field) {
+                return false;
             }
+            schema.composite_defs().all(|(attribute_name, composite)| {
+                let fed = composite
+                    .segments
+                    .iter()
+                    .any(|segment| segment.struct_field_name == field);
+                !fed || (attribute_name == field && composite.bare_field() == Some(&field))
+            })
+        })
+        .collect();
+    if counters.is_empty() {
+        return quote! {};
+    }
+
+    let trait_name = format_ident!("{}Counters", name);
+    let doc = format!("Atomic counter updates for the integer fields of [`{name}`].");
+    let mut signatures = vec![];
+    let mut impls = vec![];
+    for (ident, ty) in counters {
+        let attribute = ident.to_string();
+        let increment = format_ident!("increment_{}", ident);
+        let decrement = format_ident!("decrement_{}", ident);
+        let returning = format_ident!("send_returning_{}", ident);
+        let returning_doc = format!(
+            "Applies the update and returns the new `{attribute}`, without reading back the rest of the item."
+        );
+        signatures.push(quote! {
+            fn #increment(self, by: #ty) -> Self;
+            fn #decrement(self, by: #ty) -> Self;
+            #[doc = #returning_doc]
+            fn #returning(
+                self,
+            ) -> impl ::std::future::Future<Output = Result<#ty, entity_core::Error>> + Send
+            where
+                Self: Into<entity_core::UpdateBuilder<#name>>;
+        });
+        impls.push(quote! {
+            fn #increment(mut self, by: #ty) -> Self {
+                self.inner_mut()
+                    .updates
+                    .push(entity_core::AttributeUpdate::increment(#attribute, by));
+                self
+            }
+            fn #decrement(mut self, by: #ty) -> Self {
+                self.inner_mut()
+                    .updates
+                    .push(entity_core::AttributeUpdate::decrement(#attribute, by));
+                self
+            }
+            fn #returning(
+                self,
+            ) -> impl ::std::future::Future<Output = Result<#ty, entity_core::Error>> + Send
+            where
+                Self: Into<entity_core::UpdateBuilder<#name>>,
+            {
+                let update: entity_core::UpdateBuilder<#name> = self.into();
+                async move { update.send_counter(#attribute).await }
+            }
+        });
+    }
+
+    quote! {
+        #[doc = #doc]
+        #vis trait #trait_name: entity_core::HasInner<#name> + Sized {
+            #( #signatures )*
         }
-    } else {
-        quote! {
-        {
-            // Return an empty HashMap if no sort key is defined
-            ::std::collections::HashMap::<String, String>::new()
+
+        impl<B: entity_core::HasInner<#name>> #trait_name for B {
+            #( #impls )*
         }
     }
-    };    //
-    // ─── NKS ─────────────────────────────────────────────
-    //
-    let nk_inserts = schema.non_key_defs.iter().map(|nk| {
-        let name = &nk.attribute_name;
-        match &nk.attribute_value {
-            AttributeValue::Static(v) => {
-                let val = v.clone();
-                quote! {
-                    map.insert(#name.to_string(), serde_json::Value::String(#val.to_string()));
+}
+
+//
+// ─── CONDITION FIELDS ────────────────────────────────
+//
+/// `<Entity>Fields`, handing out a typed [`entity_core::Field`] per struct field
+/// for conditional writes. Byte buffers and fields whose attribute is replaced
+/// by a composite of the same name cannot be compared, so they are left out.
+fn generate_fields(input: &DeriveInput, fields: &syn::Fields, schema: &SchemaV2) -> TokenStream {
+    let name = &input.ident;
+    let vis = &input.vis;
+    let fields_name = format_ident!("{}Fields", name);
+    let doc = format!("The fields of [`{name}`], for building write conditions.");
+    let accessors = fields
+        .iter()
+        .filter(|field| !is_bytes(&field.ty))
+        .filter_map(|field| Some((field.ident.as_ref()?, &field.ty)))
+        .filter(|(ident, _)| {
+            let field = ident.to_string();
+            schema.composite_defs().all(|(attribute_name, composite)| {
+                attribute_name != field || composite.bare_field() == Some(&field)
+            })
+        })
+        .map(|(ident, ty)| {
+            let attribute = ident.to_string();
+            let ty = option_inner(ty).unwrap_or(ty);
+            quote! {
+                pub const fn #ident() -> entity_core::Field<#name, #ty> {
+                    entity_core::Field::new(#attribute)
                 }
             }
-            AttributeValue::Composite(CompositeAttributeValue { segments, .. }) => {
-                let segs = segments.iter().map(|seg| {
-                    let field =
-                        syn::Ident::new(&seg.struct_field_name, proc_macro2::Span::call_site());
-                    if let Some(pfx) = &seg.prefix {
-                        quote! { format!("{}#{}", #pfx, self.#field) }
-                    } else {
-                        quote! { self.#field.to_string() }
+        });
+
+    quote! {
+        #[doc = #doc]
+        #vis struct #fields_name;
+
+        impl #fields_name {
+            #( #accessors )*
+        }
+    }
+}
+
+//
+// ─── KEY STRUCT ──────────────────────────────────────
+//
+/// `<Entity>Key`, holding the fields the table pk and sk are built from.
+fn generate_key_struct(
+    input: &DeriveInput,
+    fields: &syn::Fields,
+    schema: &SchemaV2,
+) -> TokenStream {
+    let name = &input.ident;
+    let vis = &input.vis;
+    let key_name = format_ident!("{}Key", name);
+
+    // Only the table keys, without nks and indexes
+    let key_schema = SchemaV2 {
+        partition_key_def: schema.partition_key_def.clone(),
+        sort_key_def: schema.sort_key_def.clone(),
+        non_key_defs: vec![],
+        index_defs: vec![],
+        delimiter: schema.delimiter,
+        version_attribute: None,
+        table_name: None,
+    };
+    let key_fields: Vec<&syn::Field> = {
+        let segment_fields = segment_fields(&key_schema);
+        fields
+            .iter()
+            .filter(|field| {
+                field
+                    .ident
+                    .as_ref()
+                    .is_some_and(|ident| segment_fields.contains_key(ident.to_string().as_str()))
+            })
+            .collect()
+    };
+
+    let key_idents: Vec<&syn::Ident> = key_fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .collect();
+    let key_types = key_fields.iter().map(|field| &field.ty);
+    let value_inserts = tok_value_inserts(&key_schema, |ident| quote! { (&self.#ident) });
+    let attribute_inserts = key_fields.iter().filter_map(|field| {
+        let ident = field.ident.as_ref()?;
+        let field_name = ident.to_string();
+        let value = if is_bytes(&field.ty) {
+            quote! { entity_core::binary_attribute(&self.#ident) }
+        } else {
+            quote! { entity_core::serialize_attribute(&self.#ident) }
+        };
+        Some(quote! {
+            item.insert(#field_name.to_string(), #value);
+        })
+    });
+    let doc = format!("The table keys of [`{name}`], for point reads and deletes.");
+
+    quote! {
+        #[doc = #doc]
+        #[derive(Debug, Clone, PartialEq)]
+        #vis struct #key_name {
+            #( pub #key_idents: #key_types, )*
+        }
+
+        impl entity_core::PrimaryKey for #key_name {
+            type Entity = #name;
+
+            fn segment_values(
+                &self,
+            ) -> Result<::std::collections::HashMap<String, String>, entity_core::ItemError> {
+                let mut values = ::std::collections::HashMap::new();
+                #( #value_inserts )*
+                Ok(values)
+            }
+
+            fn field_attributes(&self) -> entity_core::Item {
+                let mut item = entity_core::Item::new();
+                #( #attribute_inserts )*
+                item
+            }
+        }
+
+        impl #name {
+            pub fn key(&self) -> #key_name {
+                #key_name {
+                    #( #key_idents: self.#key_idents.clone(), )*
+                }
+            }
+        }
+    }
+}
+
+//
+// ─── SK PREFIX ───────────────────────────────────────
+//
+/// `<Entity>SkPrefix<N>`, which renders the first `N` segments of a composite
+/// sort key. Each stage only has a method for the next segment, so leading
+/// segments cannot be skipped.
+fn generate_sk_prefix(input: &DeriveInput, fields: &syn::Fields, schema: &SchemaV2) -> TokenStream {
+    let Some(KeyDef {
+        attribute_value: AttributeValue::Composite(composite),
+        ..
+    }) = &schema.sort_key_def
+    else {
+        return quote! {};
+    };
+
+    let name = &input.ident;
+    let vis = &input.vis;
+    let prefix_name = format_ident!("{}SkPrefix", name);
+
+    let stages = composite
+        .segments
+        .iter()
+        .enumerate()
+        .filter_map(|(n, segment)| {
+            let field_name = &segment.struct_field_name;
+            let field = fields.iter().find(|field| {
+                field
+                    .ident
+                    .as_ref()
+                    .is_some_and(|ident| ident == field_name)
+            })?;
+            let ident = field.ident.as_ref()?;
+            let ty = &field.ty;
+            let next = n + 1;
+            let rendered = tok_render_segment(segment, &quote! { (&#ident) });
+            // A formatted segment may fail to render, so its stage does too
+            let stage = match segment.format {
+                Some(_) => quote! {
+                    pub fn #ident(
+                        mut self,
+                        #ident: #ty,
+                    ) -> Result<#prefix_name<#next>, entity_core::ItemError> {
+                        self.values.insert(#field_name.to_string(), #rendered);
+                        Ok(#prefix_name { values: self.values })
                     }
-                });
-                quote! {
-                    map.insert(#name.to_string(),
-                        serde_json::Value::String(Vec::<String>::from([ #( #segs ),* ]).join("#")));
+                },
+                None => quote! {
+                    pub fn #ident(mut self, #ident: #ty) -> #prefix_name<#next> {
+                        self.values.insert(#field_name.to_string(), #rendered);
+                        #prefix_name { values: self.values }
+                    }
+                },
+            };
+            Some(quote! {
+                impl #prefix_name<#n> {
+                    #stage
                 }
+            })
+        });
+    let doc = format!(
+        "Leading segments of [`{name}`]'s sort key, for `begins_with` conditions. \
+         Start with `{name}::sk_prefix()`."
+    );
+
+    quote! {
+        #[doc = #doc]
+        #vis struct #prefix_name<const N: usize> {
+            values: ::std::collections::HashMap<String, String>,
+        }
+
+        #( #stages )*
+
+        impl<const N: usize> ::std::fmt::Display for #prefix_name<N> {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                let schema = <#name as entity_core::Entity2>::get_schema();
+                let Some(entity_core::KeyDef {
+                    attribute_value: entity_core::AttributeValue::Composite(composite),
+                    ..
+                }) = &schema.sort_key_def
+                else {
+                    unreachable!()
+                };
+                f.write_str(&composite.render_prefix(&self.values, schema.delimiter))
             }
         }
-    });
 
-    // --- final impl ---
+        impl #name {
+            pub fn sk_prefix() -> #prefix_name<0> {
+                #prefix_name {
+                    values: ::std::collections::HashMap::new(),
+                }
+            }
+        }
+    }
+}
+
+pub fn generate_enum_impl(input: &DeriveInput, entity: EnumEntity) -> TokenStream {
+    let name = &input.ident;
+    let schema_tokens = tok_schema(&entity.schema);
+    let discriminator = &entity.discriminator;
+
+    let mut schema_arms = vec![];
+    let mut variant_schemas = vec![];
+    let mut value_arms = vec![];
+    let mut attribute_arms = vec![];
+    let mut from_item_arms = vec![];
+    for variant in &entity.variants {
+        let ident = &variant.ident;
+        let tag = &variant.tag;
+        let variant_schema = tok_schema(&variant.schema);
+
+        schema_arms.push(quote! {
+            Self::#ident { .. } => #variant_schema,
+        });
+        variant_schemas.push(variant_schema.clone());
+
+        // Variant fields are bound by reference
+        let segment_idents: Vec<syn::Ident> = {
+            let mut names: Vec<&str> = segment_fields(&variant.schema).into_keys().collect();
+            names.sort();
+            names
+                .into_iter()
+                .map(|field| syn::Ident::new(field, proc_macro2::Span::call_site()))
+                .collect()
+        };
+        let value_inserts = tok_value_inserts(&variant.schema, |ident| quote! { #ident });
+        value_arms.push(quote! {
+            Self::#ident { #( #segment_idents, )* .. } => {
+                #( #value_inserts )*
+            }
+        });
+
+        let field_idents: Vec<&syn::Ident> = variant
+            .fields
+            .iter()
+            .filter_map(|field| field.ident.as_ref())
+            .collect();
+        let attribute_inserts = variant.fields.iter().filter_map(|field| {
+            let field_ident = field.ident.as_ref()?;
+            let field_name = field_ident.to_string();
+            let value = if is_bytes(&field.ty) {
+                quote! { entity_core::binary_attribute(#field_ident) }
+            } else {
+                quote! { entity_core::serialize_attribute(#field_ident) }
+            };
+            Some(quote! {
+                item.insert(#field_name.to_string(), #value);
+            })
+        });
+        attribute_arms.push(quote! {
+            Self::#ident { #( #field_idents ),* } => {
+                #( #attribute_inserts )*
+            }
+        });
+
+        let field_inits = tok_field_inits(&variant.fields, &variant.schema);
+        from_item_arms.push(quote! {
+            #tag => {
+                let segments = #variant_schema.decode_segments(item)?;
+                Ok(Self::#ident {
+                    #( #field_inits ),*
+                })
+            }
+        });
+    }
+
     quote! {
         impl entity_core::Entity2 for #name {
+            /// The keys every variant shares
             fn get_schema() -> entity_core::SchemaV2 {
-                let partition_key_def = #partition_key_def_tokens;
-                let sort_key_def = #sort_key_def_tokens;
-                let non_key_defs: Vec<entity_core::KeyDef<entity_core::AttributeValue>> = #nk_items;
-
-                entity_core::SchemaV2 {
-                    partition_key_def,
-                    sort_key_def,
-                    non_key_defs,
-                }
+                #schema_tokens
             }
 
-            /// Serialize to `serde_json::Value`
-            fn to_item(&self) -> serde_json::Value {
-                let mut map = serde_json::Map::new();
+            fn schema(&self) -> entity_core::SchemaV2 {
+                match self {
+                    #( #schema_arms )*
+                }
+            }
 
-                // pk
-                map.insert(#pk_attr_name.to_string(), serde_json::Value::String(#pk_expr));
+            fn variant_schemas() -> Vec<entity_core::SchemaV2> {
+                vec![ #( #variant_schemas ),* ]
+            }
 
-                // sk
-                let sk_map: ::std::collections::HashMap<String, String> = #sk_expr;
-                for (key, value) in sk_map {
-                    map.insert(key, serde_json::Value::String(value));
+            fn segment_values(
+                &self,
+            ) -> Result<::std::collections::HashMap<String, String>, entity_core::ItemError> {
+                let mut values = ::std::collections::HashMap::new();
+                match self {
+                    #( #value_arms )*
                 }
+                Ok(values)
+            }
 
-                // NKs
-                #( #nk_inserts )*
+            fn field_attributes(&self) -> entity_core::Item {
+                let mut item = entity_core::Item::new();
+                match self {
+                    #( #attribute_arms )*
+                }
+                item
+            }
 
-                serde_json::Value::Object(map)
+            /// Deserialize from `serde_json::Value`, picking the variant from its tag
+            fn from_item(
+                item: &entity_core::__private::serde_json::Value,
+            ) -> Result<Self, entity_core::ItemError> {
+                let tag: String = entity_core::field_value(item, #discriminator)?;
+                match tag.as_str() {
+                    #( #from_item_arms )*
+                    _ => Err(entity_core::ItemError::MalformedAttribute {
+                        attribute: #discriminator.to_string(),
+                        value: tag,
+                    }),
+                }
             }
         }
     }
 }
-
-// fn to_item(&self) -> serde_json::Value {
-//     let mut map = serde_json::Map::new();
-//
-//     // PK
-//     let pk_val = #pk_expr;
-//     map.insert("pk".to_string(), serde_json::Value::String(pk_val));
-//
-//     // SK
-//     if let Some(sk_val) = #sk_expr {
-//         map.insert("sk".to_string(), serde_json::Value::String(sk_val));
-//     }
-//
-//     // NKs
-//     #( #nk_inserts )*
-//
-//         serde_json::Value::Object(map)
-// }
\ No newline at end of file
@@ -1,5 +1,5 @@
-mod parser;
 mod codegen;
+mod parser;
 mod schema;
 
 use proc_macro::TokenStream;
@@ -8,8 +8,9 @@ use syn::{DeriveInput, ItemStruct, parse_macro_input};
 
 const PARTITION: &str = "partition_key";
 const SORT: &str = "sort";
+const VERSION: &str = "version";
 
-#[proc_macro_derive(Dynodmize, attributes(pk, sk, nk))]
+#[proc_macro_derive(Dynodmize, attributes(dynodmize, pk, sk, nk, gsi, lsi, version))]
 pub fn derive_entity(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as syn::DeriveInput);
     parser::expand_entity(&input).into()
@@ -31,6 +32,7 @@ pub fn derive_entity_model(input: TokenStream) -> TokenStream {
             let typ = &field.ty;
 
             let mut is_pk = false;
+            let mut is_version = false;
             for attribute in &field.attrs {
                 if attribute.path().is_ident(PARTITION) {
                     pk_field = Some(ident.clone());
@@ -39,10 +41,14 @@ pub fn derive_entity_model(input: TokenStream) -> TokenStream {
                 if attribute.path().is_ident(SORT) {
                     sort_fields.push(ident.clone());
                 }
+                if attribute.path().is_ident(VERSION) {
+                    is_version = true;
+                }
             }
 
-            // Generate setters for all non-PK fields
-            if !is_pk {
+            // Generate setters for all non-PK fields. The version only changes
+            // with each write.
+            if !is_pk && !is_version {
                 let method_name = syn::Ident::new(&format!("set_{}", ident), ident.span());
 
                 // Trait method signature (owned-builder style)
@@ -50,13 +56,17 @@ pub fn derive_entity_model(input: TokenStream) -> TokenStream {
                     fn #method_name(self, value: #typ) -> Self;
                 });
 
-                // Impl: mutate inner, return Self
+                // Impl: record the change on inner, return Self. Bytes stay
+                // binary, as `to_attribute_map` writes them.
+                let attribute = ident.to_string();
+                let update = if codegen::is_bytes(typ) {
+                    quote! { entity_core::AttributeUpdate::set_bytes(#attribute, &value) }
+                } else {
+                    quote! { entity_core::AttributeUpdate::set(#attribute, &value) }
+                };
                 impls.push(quote! {
                     fn #method_name(mut self, value: #typ) -> Self {
-                        let v = value.clone();
-                        self.inner_mut().updates.push(Box::new(move |e: &mut #name| {
-                            e.#ident = v.clone();
-                        }));
+                        self.inner_mut().updates.push(#update);
                         self
                     }
                 });
@@ -104,23 +114,61 @@ pub fn derive_entity_model(input: TokenStream) -> TokenStream {
         impl #setters_trait for entity_core::UpdateBuilderWithSetters<#name> {
             #(#impls)*
         }
-
-        // Hook the wrapper into HasInner so setters can reach the inner builder
-        impl entity_core::HasInner<#name> for entity_core::UpdateBuilderWithSetters<#name> {
-            fn inner_mut(&mut self) -> &mut entity_core::UpdateBuilder<#name> {
-                &mut self.inner
-            }
-        }
     };
 
     expanded.into()
 }
 
+/// `#[based_on(Entity)]` or `#[based_on(Entity, tables = field)]`, where
+/// `field` is the repository's `TableConfig`.
+struct BasedOnArgs {
+    entity_ty: syn::Type,
+    tables: Option<syn::Ident>,
+}
+
+impl syn::parse::Parse for BasedOnArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let entity_ty = input.parse()?;
+        let mut tables = None;
+        if input.parse::<Option<syn::Token![,]>>()?.is_some() && !input.is_empty() {
+            let key: syn::Ident = input.parse()?;
+            if key != "tables" {
+                return Err(syn::Error::new_spanned(key, "expected `tables = field`"));
+            }
+            input.parse::<syn::Token![=]>()?;
+            tables = Some(input.parse()?);
+            input.parse::<Option<syn::Token![,]>>()?;
+        }
+        Ok(BasedOnArgs { entity_ty, tables })
+    }
+}
+
 #[proc_macro_attribute]
 pub fn based_on(args: TokenStream, input: TokenStream) -> TokenStream {
-    let entity_ty: syn::Type = syn::parse(args).expect("expected a type in #[based_on(..)]");
-    let repo_struct: ItemStruct = syn::parse(input).expect("expected a struct after #[based_on]");
+    let BasedOnArgs { entity_ty, tables } = parse_macro_input!(args as BasedOnArgs);
+    let repo_struct = parse_macro_input!(input as ItemStruct);
     let repo_name = &repo_struct.ident;
+    // The named field's `TableConfig` goes to every builder the repository makes
+    let tables = match tables {
+        Some(field) => {
+            let has_field = repo_struct
+                .fields
+                .iter()
+                .any(|f| f.ident.as_ref() == Some(&field));
+            if !has_field {
+                return syn::Error::new_spanned(
+                    &field,
+                    format!("`{repo_name}` has no field `{field}`"),
+                )
+                .to_compile_error()
+                .into();
+            }
+            quote! { .tables(self.#field.clone()) }
+        }
+        None => quote! {},
+    };
+
+    let client = quote! { entity_core::__private::aws_sdk_dynamodb::Client };
 
     let expanded = quote! {
         use entity_core::UpdateBuilderWithSetters;
@@ -129,26 +177,46 @@ pub fn based_on(args: TokenStream, input: TokenStream) -> TokenStream {
 
         impl #repo_name {
             /// Hello
-            pub fn create(&self, entity: #entity_ty, client: Client)
+            pub fn create(&self, entity: #entity_ty, client: #client)
                 -> entity_core::CreateBuilder<#entity_ty>
             {
-                entity_core::CreateBuilder { entity, client }
+                entity_core::CreateBuilder::new(entity, client)#tables
             }
 
-            pub fn query(&self) -> entity_core::QueryBuilder<#entity_ty> {
-                entity_core::QueryBuilder {
-                    partition_key: None,
-                    _marker: std::marker::PhantomData,
-                }
+            pub fn get<K>(&self, key: K, client: #client) -> entity_core::GetBuilder<K>
+            where
+                K: entity_core::PrimaryKey<Entity = #entity_ty>,
+            {
+                entity_core::GetBuilder::new(key, client)#tables
             }
 
-            pub fn update(&self) -> UpdateBuilderWithSetters<#entity_ty> {
-                UpdateBuilderWithSetters {
-                    inner: entity_core::UpdateBuilder {
-                        partition_key: None,
-                        updates: vec![],
-                    }
-                }
+            pub fn delete<K>(&self, key: K, client: #client) -> entity_core::DeleteBuilder<K>
+            where
+                K: entity_core::PrimaryKey<Entity = #entity_ty>,
+            {
+                entity_core::DeleteBuilder::new(key, client)#tables
+            }
+
+            pub fn query(&self, client: #client) -> entity_core::QueryBuilder<#entity_ty> {
+                entity_core::QueryBuilder::new(client)#tables
+            }
+
+            pub fn scan(&self, client: #client) -> entity_core::ScanBuilder<#entity_ty> {
+                entity_core::ScanBuilder::new(client)#tables
+            }
+
+            /// Fails if the key cannot be rendered.
+            pub fn update<K>(
+                &self,
+                key: K,
+                client: #client,
+            ) -> Result<UpdateBuilderWithSetters<#entity_ty>, entity_core::ItemError>
+            where
+                K: entity_core::PrimaryKey<Entity = #entity_ty>,
+            {
+                Ok(UpdateBuilderWithSetters {
+                    inner: entity_core::UpdateBuilder::new(key.to_key()?, client)#tables,
+                })
             }
         }
     };
@@ -1,5 +1,5 @@
 use crate::{codegen, schema};
-use entity_core::SchemaV2;
+use entity_core::{IndexKind, ScalarType, SchemaV2, SegmentEncoding};
 use proc_macro2::{Span, TokenStream};
 use std::collections::HashSet;
 use syn::parse::Parser;
@@ -9,16 +9,154 @@ use syn::{Data, DeriveInput, Error, Lit, Meta};
 
 pub fn expand_entity(input: &DeriveInput) -> TokenStream {
     match parse_entity(input) {
-        Ok(schema) => codegen::generate_impl(input, schema),
+        Ok(ParsedEntity::Struct(schema)) => codegen::generate_impl(input, schema),
+        Ok(ParsedEntity::Enum(entity)) => codegen::generate_enum_impl(input, entity),
         Err(err) => err.to_compile_error(),
     }
 }
 
-fn parse_entity(input: &DeriveInput) -> Result<SchemaV2, Error> {
-    let (pk_def, sk_def, nk_defs) = parse_entity_attrs(input)?;
-    let field_infos = parse_struct_fields(input)?;
-    let schema = schema::build_schema(pk_def, sk_def, nk_defs, field_infos)?;
-    Ok(schema)
+pub enum ParsedEntity {
+    Struct(SchemaV2),
+    Enum(EnumEntity),
+}
+
+/// An enum whose variants are item types sharing one collection
+pub struct EnumEntity {
+    /// What every variant shares, returned by `get_schema`
+    pub schema: SchemaV2,
+    /// Attribute holding each item's variant tag
+    pub discriminator: String,
+    pub variants: Vec<VariantEntity>,
+}
+
+pub struct VariantEntity {
+    pub ident: syn::Ident,
+    pub tag: String,
+    pub fields: syn::Fields,
+    pub schema: SchemaV2,
+}
+
+/// Default attribute holding the variant tag of an enum entity
+const DEFAULT_DISCRIMINATOR: &str = "entity_type";
+
+fn parse_entity(input: &DeriveInput) -> Result<ParsedEntity, Error> {
+    match &input.data {
+        Data::Struct(data_struct) => {
+            let (pk_def, sk_def, nk_defs, index_defs, options) = parse_entity_attrs(&input.attrs)?;
+            if options.discriminator.is_some() || options.tag.is_some() {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "`discriminator` and `tag` only apply to enums",
+                ));
+            }
+            let field_infos = parse_fields(&data_struct.fields)?;
+            let mut schema =
+                schema::build_schema(pk_def, sk_def, nk_defs, index_defs, field_infos, options)?;
+            schema.version_attribute = parse_version_field(&data_struct.fields)?;
+            schema::validate_attribute_names(&schema, &field_idents(&data_struct.fields))?;
+            Ok(ParsedEntity::Struct(schema))
+        }
+        Data::Enum(data_enum) => parse_enum(input, data_enum).map(ParsedEntity::Enum),
+        Data::Union(_) => Err(Error::new_spanned(
+            input,
+            "Dynodmize can only be derived for structs and enums",
+        )),
+    }
+}
+
+// -------------------------------
+// Enums: one schema per variant
+// -------------------------------
+fn parse_enum(input: &DeriveInput, data_enum: &syn::DataEnum) -> Result<EnumEntity, Error> {
+    let enum_options = parse_entity_attrs(&input.attrs)?.4;
+    if enum_options.tag.is_some() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "`tag` goes on a variant, not on the enum",
+        ));
+    }
+    let discriminator = enum_options
+        .discriminator
+        .clone()
+        .unwrap_or_else(|| DEFAULT_DISCRIMINATOR.to_string());
+
+    // Key declarations on the enum apply to every variant
+    let shared_attrs: Vec<syn::Attribute> = input
+        .attrs
+        .iter()
+        .filter(|attr| is_key_attr(attr))
+        .cloned()
+        .collect();
+
+    let mut variants: Vec<VariantEntity> = vec![];
+    for variant in &data_enum.variants {
+        if !matches!(variant.fields, syn::Fields::Named(_)) {
+            return Err(Error::new_spanned(
+                variant,
+                "Dynodmize enum variants must have named fields",
+            ));
+        }
+
+        let attrs: Vec<syn::Attribute> =
+            shared_attrs.iter().chain(&variant.attrs).cloned().collect();
+        let (pk_def, sk_def, nk_defs, index_defs, variant_options) = parse_entity_attrs(&attrs)?;
+        if variant_options.delimiter.is_some()
+            || variant_options.discriminator.is_some()
+            || variant_options.table.is_some()
+        {
+            return Err(Error::new_spanned(
+                &variant.ident,
+                "`delimiter`, `discriminator` and `table` are set on the enum",
+            ));
+        }
+        let tag = variant_options
+            .tag
+            .unwrap_or_else(|| variant.ident.to_string());
+        if variants.iter().any(|other| other.tag == tag) {
+            return Err(Error::new_spanned(
+                &variant.ident,
+                format!("Another variant already uses the tag `{tag}`"),
+            ));
+        }
+
+        let options = RawEntityOptions {
+            delimiter: enum_options.delimiter,
+            table: enum_options.table.clone(),
+            ..RawEntityOptions::default()
+        };
+        if let Some(field) = version_fields(&variant.fields).next() {
+            return Err(Error::new_spanned(
+                field,
+                "#[version] is only supported on struct entities",
+            ));
+        }
+        let field_infos = parse_fields(&variant.fields)?;
+        let mut schema =
+            schema::build_schema(pk_def, sk_def, nk_defs, index_defs, field_infos, options)?;
+        schema::add_discriminator(&mut schema, &discriminator, &tag, variant.ident.span())?;
+        schema::validate_attribute_names(&schema, &field_idents(&variant.fields))?;
+
+        variants.push(VariantEntity {
+            ident: variant.ident.clone(),
+            tag,
+            fields: variant.fields.clone(),
+            schema,
+        });
+    }
+
+    let schema = schema::collection_schema(&variants, input.ident.span())?;
+    Ok(EnumEntity {
+        schema,
+        discriminator,
+        variants,
+    })
+}
+
+fn field_idents(fields: &syn::Fields) -> Vec<&syn::Ident> {
+    fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .collect()
 }
 
 //
@@ -36,6 +174,10 @@ pub struct RawPkFieldDef {
     pub field_name: String,
     pub prefix: Option<String>,
     pub order: Option<usize>,
+    pub encoding: SegmentEncoding,
+    pub format: Option<String>,
+    pub optional: bool,
+    pub scalar_type: ScalarType,
     pub span: Span,
 }
 
@@ -43,6 +185,10 @@ pub struct RawSkFieldDef {
     pub field_name: String,
     pub prefix: Option<String>,
     pub order: Option<usize>,
+    pub encoding: SegmentEncoding,
+    pub format: Option<String>,
+    pub optional: bool,
+    pub scalar_type: ScalarType,
     pub span: Span,
 }
 
@@ -51,6 +197,10 @@ pub struct RawNkFieldDef {
     pub name: String,
     pub prefix: Option<String>,
     pub order: Option<usize>,
+    pub encoding: SegmentEncoding,
+    pub format: Option<String>,
+    pub optional: bool,
+    pub scalar_type: ScalarType,
     pub span: Span,
 }
 
@@ -68,36 +218,82 @@ pub struct RawNkStructDef {
     pub(crate) static_value: Option<String>,
 }
 
-pub enum RawStructFieldDefs {
-    Pk(RawPkFieldDef),
-    Sk(RawSkFieldDef),
-    Nk(RawNkFieldDef),
+/// `#[gsi(name = ..., pk = ..., sk = ...)]` or `#[lsi(name = ..., sk = ...)]`
+pub struct RawIndexStructDef {
+    pub(crate) index_name: String,
+    pub(crate) kind: IndexKind,
+    pub(crate) pk: Option<String>,
+    pub(crate) sk: Option<String>,
+    pub(crate) span: Span,
+}
+
+/// Which key of an index a field contributes to
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndexKey {
+    Pk,
+    Sk,
+}
+
+pub struct RawIndexFieldDef {
+    pub field_name: String,
+    pub index_name: String,
+    pub key: IndexKey,
+    pub prefix: Option<String>,
+    pub order: Option<usize>,
+    pub encoding: SegmentEncoding,
+    pub format: Option<String>,
+    pub optional: bool,
+    pub scalar_type: ScalarType,
+    pub span: Span,
+}
+
+/// Entity-wide settings from `#[dynodmize(...)]`
+#[derive(Default)]
+pub struct RawEntityOptions {
+    pub(crate) delimiter: Option<char>,
+    pub(crate) discriminator: Option<String>,
+    pub(crate) tag: Option<String>,
+    pub(crate) table: Option<String>,
 }
 
-pub enum RawFieldDef {
+pub enum RawStructFieldDefs {
     Pk(RawPkFieldDef),
     Sk(RawSkFieldDef),
     Nk(RawNkFieldDef),
+    Index(RawIndexFieldDef),
 }
 
-fn parse_entity_attrs(
-    input: &DeriveInput,
-) -> Result<(Option<RawPkStructDef>, Option<RawSkStructDef>, Vec<RawNkStructDef>), syn::Error> {
+type RawStructDefs = (
+    Option<RawPkStructDef>,
+    Option<RawSkStructDef>,
+    Vec<RawNkStructDef>,
+    Vec<RawIndexStructDef>,
+    RawEntityOptions,
+);
+
+fn parse_entity_attrs(attrs: &[syn::Attribute]) -> Result<RawStructDefs, syn::Error> {
     let mut pk: Option<RawPkStructDef> = None;
     let mut sk: Option<RawSkStructDef> = None;
     let mut nks: Vec<RawNkStructDef> = vec![];
+    let mut indexes: Vec<RawIndexStructDef> = vec![];
+    let mut options = RawEntityOptions::default();
 
     // A struct can have multiple attributes
-    for attr in &input.attrs {
+    for attr in attrs {
         // ---------------
         // Attribute-level
         // ---------------
 
+        if attr.path().is_ident("dynodmize") {
+            parse_entity_options(attr, &mut options)?;
+            continue;
+        }
+
         // Guard
-        if !(attr.path().is_ident("pk") || attr.path().is_ident("sk") || attr.path().is_ident("nk"))
-        {
+        if !is_key_attr(attr) {
             continue;
         }
+        let is_index = attr.path().is_ident("gsi") || attr.path().is_ident("lsi");
 
         if let Meta::List(list) = &attr.meta {
             // -----------------
@@ -107,6 +303,8 @@ fn parse_entity_attrs(
             let mut value_prefix = None;
             let mut value_suffix = None;
             let mut static_value = None;
+            let mut index_pk = None;
+            let mut index_sk = None;
 
             let parsed =
                 Punctuated::<Meta, syn::Token![,]>::parse_terminated.parse2(list.tokens.clone())?;
@@ -120,9 +318,15 @@ fn parse_entity_attrs(
                     if let syn::Expr::Lit(expr_lit) = &nv.value {
                         match (&key[..], &expr_lit.lit) {
                             ("name", Lit::Str(s)) => name = Some(s.value()),
-                            ("value_prefix", Lit::Str(s)) => value_prefix = Some(s.value()),
-                            ("value_suffix", Lit::Str(s)) => value_suffix = Some(s.value()),
-                            ("value", Lit::Str(s)) => static_value = Some(s.value()),
+                            ("value_prefix", Lit::Str(s)) if !is_index => {
+                                value_prefix = Some(s.value())
+                            }
+                            ("value_suffix", Lit::Str(s)) if !is_index => {
+                                value_suffix = Some(s.value())
+                            }
+                            ("value", Lit::Str(s)) if !is_index => static_value = Some(s.value()),
+                            ("pk", Lit::Str(s)) if is_index => index_pk = Some(s.value()),
+                            ("sk", Lit::Str(s)) if is_index => index_sk = Some(s.value()),
                             _ => {
                                 return Err(Error::new_spanned(
                                     nv,
@@ -164,7 +368,9 @@ fn parse_entity_attrs(
             }
 
             if attr.path().is_ident("nk") {
-                let name = name.ok_or_else(|| Error::new_spanned(attr, "nk must have a name"))?;
+                let name = name
+                    .clone()
+                    .ok_or_else(|| Error::new_spanned(attr, "nk must have a name"))?;
                 nks.push(RawNkStructDef {
                     name,
                     value_prefix,
@@ -172,42 +378,176 @@ fn parse_entity_attrs(
                     static_value,
                 });
             }
+
+            if is_index {
+                let index_name =
+                    name.ok_or_else(|| Error::new_spanned(attr, "index must have a name"))?;
+                if indexes.iter().any(|index| index.index_name == index_name) {
+                    return Err(Error::new_spanned(
+                        attr,
+                        format!("Index `{index_name}` is declared more than once"),
+                    ));
+                }
+                let kind = if attr.path().is_ident("gsi") {
+                    if index_pk.is_none() {
+                        return Err(Error::new_spanned(attr, "gsi must name its pk attribute"));
+                    }
+                    IndexKind::Global
+                } else {
+                    if index_pk.is_some() {
+                        return Err(Error::new_spanned(
+                            attr,
+                            "lsi shares the table's pk and cannot name its own",
+                        ));
+                    }
+                    if index_sk.is_none() {
+                        return Err(Error::new_spanned(attr, "lsi must name its sk attribute"));
+                    }
+                    IndexKind::Local
+                };
+                indexes.push(RawIndexStructDef {
+                    index_name,
+                    kind,
+                    pk: index_pk,
+                    sk: index_sk,
+                    span: attr.span(),
+                });
+            }
         } else {
             return Err(Error::new_spanned(
                 attr,
-                "Expected #[pk(...)] or #[sk(...)]",
+                "Expected #[pk(...)], #[sk(...)], #[nk(...)], #[gsi(...)] or #[lsi(...)]",
             ));
         }
     }
 
-    Ok((pk, sk, nks))
+    Ok((pk, sk, nks, indexes, options))
+}
+
+fn is_key_attr(attr: &syn::Attribute) -> bool {
+    ["pk", "sk", "nk", "gsi", "lsi"]
+        .iter()
+        .any(|ident| attr.path().is_ident(ident))
+}
+
+// -----------------------
+// #[dynodmize(... = ...)]
+// -----------------------
+fn parse_entity_options(
+    attr: &syn::Attribute,
+    options: &mut RawEntityOptions,
+) -> Result<(), syn::Error> {
+    let Meta::List(list) = &attr.meta else {
+        return Err(Error::new_spanned(attr, "Expected #[dynodmize(...)]"));
+    };
+    let parsed =
+        Punctuated::<Meta, syn::Token![,]>::parse_terminated.parse2(list.tokens.clone())?;
+
+    for nested in parsed {
+        let Meta::NameValue(nv) = &nested else {
+            return Err(Error::new_spanned(nested, "Expected `key = value`"));
+        };
+        let key = nv.path.get_ident().map(|ident| ident.to_string());
+        let syn::Expr::Lit(expr_lit) = &nv.value else {
+            return Err(Error::new_spanned(&nv.value, "Expected a literal"));
+        };
+        match (key.as_deref(), &expr_lit.lit) {
+            (Some("delimiter"), Lit::Str(s)) => {
+                let value = s.value();
+                let mut chars = value.chars();
+                let (Some(delimiter), None) = (chars.next(), chars.next()) else {
+                    return Err(Error::new_spanned(
+                        s,
+                        "delimiter must be a single character",
+                    ));
+                };
+                if delimiter == entity_core::ESCAPE {
+                    return Err(Error::new_spanned(
+                        s,
+                        "`%` escapes segment values and cannot be the delimiter",
+                    ));
+                }
+                options.delimiter = Some(delimiter);
+            }
+            (Some("discriminator"), Lit::Str(s)) => options.discriminator = Some(s.value()),
+            (Some("tag"), Lit::Str(s)) => options.tag = Some(s.value()),
+            (Some("table"), Lit::Str(s)) if s.value().is_empty() => {
+                return Err(Error::new_spanned(s, "table name cannot be empty"));
+            }
+            (Some("table"), Lit::Str(s)) => options.table = Some(s.value()),
+            _ => return Err(Error::new_spanned(nv, "Unknown #[dynodmize] option")),
+        }
+    }
+
+    Ok(())
 }
 
 //
 // ─── FIELD LEVEL ATTRS ──────────────────────────────────────────────────────────
 //
 
-fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, syn::Error> {
-    let mut all_field_defs = vec![];
+fn version_fields(fields: &syn::Fields) -> impl Iterator<Item = &syn::Field> {
+    fields.iter().filter(|field| {
+        field
+            .attrs
+            .iter()
+            .any(|attr| attr.path().is_ident("version"))
+    })
+}
 
-    let Data::Struct(data_struct) = &input.data else {
+// ----------
+// #[version]
+// ----------
+/// The attribute of the field marked `#[version]`. It is a plain integer that
+/// no key is built from, since every write changes it.
+fn parse_version_field(fields: &syn::Fields) -> Result<Option<String>, syn::Error> {
+    let mut version_fields = version_fields(fields);
+    let Some(field) = version_fields.next() else {
+        return Ok(None);
+    };
+    if let Some(other) = version_fields.next() {
         return Err(Error::new_spanned(
-            input,
-            "Dynodmize can only be derived for structs",
+            other,
+            "Only one field can be the #[version]",
         ));
-    };
+    }
+    for attr in &field.attrs {
+        if attr.path().is_ident("version") && !matches!(attr.meta, Meta::Path(_)) {
+            return Err(Error::new_spanned(attr, "Expected #[version]"));
+        }
+        if is_key_attr(attr) {
+            return Err(Error::new_spanned(
+                attr,
+                "The #[version] field cannot be part of a key",
+            ));
+        }
+    }
+    if !codegen::is_integer(&field.ty) {
+        return Err(Error::new_spanned(
+            &field.ty,
+            "#[version] needs an integer field",
+        ));
+    }
+    Ok(field.ident.as_ref().map(|ident| ident.to_string()))
+}
+
+fn parse_fields(fields: &syn::Fields) -> Result<Vec<RawStructFieldDefs>, syn::Error> {
+    let mut all_field_defs = vec![];
 
     // Every struct has several fields
-    for field in &data_struct.fields {
+    for field in fields {
         // -----------
         // Field-level
         // -----------
 
         let ident = field.ident.as_ref().unwrap();
+        let optional = option_inner(&field.ty).is_some();
+        let scalar_type = scalar_type(&field.ty);
 
         let mut pk_defs: Vec<RawPkFieldDef> = vec![];
         let mut sk_defs: Vec<RawSkFieldDef> = vec![];
         let mut nk_defs: Vec<RawNkFieldDef> = vec![];
+        let mut index_defs: Vec<RawIndexFieldDef> = vec![];
 
         // Every field can have several attributes
         for attr in &field.attrs {
@@ -216,10 +556,7 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
             // ---------------
 
             // Guard
-            if !(attr.path().is_ident("pk")
-                || attr.path().is_ident("sk")
-                || attr.path().is_ident("nk"))
-            {
+            if !is_key_attr(attr) {
                 continue;
             }
 
@@ -227,6 +564,10 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
             let mut prefix = None;
             let mut order: Option<usize> = None;
             let mut name: Option<String> = None;
+            let mut pad: Option<usize> = None;
+            let mut sortable = false;
+            let mut format: Option<String> = None;
+            let mut index_key: Option<String> = None;
 
             match &attr.meta {
                 // -----------------
@@ -240,6 +581,16 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
                         // ---------------------
                         // Field-level attribute
                         // ---------------------
+                        if let Meta::Path(flag) = &nested {
+                            if flag.is_ident("sortable") {
+                                sortable = true;
+                            } else {
+                                return Err(Error::new_spanned(
+                                    flag,
+                                    "Unknown field-level attribute",
+                                ));
+                            }
+                        }
                         if let Meta::NameValue(nv) = nested {
                             let key = nv.path.get_ident().unwrap().to_string();
                             if let syn::Expr::Lit(expr_lit) = &nv.value {
@@ -247,6 +598,12 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
                                     ("prefix", Lit::Str(s)) => prefix = Some(s.value()),
                                     ("order", Lit::Int(i)) => order = Some(i.base10_parse()?),
                                     ("name", Lit::Str(s)) => name = Some(s.value()),
+                                    ("pad", Lit::Int(i)) => pad = Some(i.base10_parse()?),
+                                    ("format", Lit::Str(s)) => {
+                                        check_format(&field.ty, s)?;
+                                        format = Some(s.value());
+                                    }
+                                    ("key", Lit::Str(s)) => index_key = Some(s.value()),
                                     _ => {
                                         return Err(Error::new_spanned(
                                             nv,
@@ -259,12 +616,23 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
                     }
 
                     // let order = order.ok_or_else(|| Error::new_spanned(attr, "Missing order ="))?;
+                    let encoding = segment_encoding(field, pad, sortable, list)?;
+                    if format.is_some() && encoding != SegmentEncoding::Plain {
+                        return Err(Error::new_spanned(
+                            list,
+                            "`format` cannot be combined with `pad` or `sortable`",
+                        ));
+                    }
 
                     if attr.path().is_ident("pk") {
                         pk_defs.push(RawPkFieldDef {
                             field_name: name.clone().unwrap_or_else(|| ident.to_string()),
                             prefix: prefix.clone(),
                             order,
+                            encoding,
+                            format: format.clone(),
+                            optional,
+                            scalar_type,
                             span: list.span(),
                         })
                     }
@@ -274,20 +642,68 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
                             field_name: name.clone().unwrap_or_else(|| ident.to_string()),
                             prefix: prefix.clone(),
                             order,
+                            encoding,
+                            format: format.clone(),
+                            optional,
+                            scalar_type,
                             span: list.span(),
                         })
                     }
 
                     if attr.path().is_ident("nk") {
                         let name = name
+                            .clone()
                             .ok_or_else(|| Error::new_spanned(attr, "nk field must have name"))?;
                         nk_defs.push(RawNkFieldDef {
-                            name: name.clone(),
-                            prefix,
+                            name,
+                            prefix: prefix.clone(),
                             order,
+                            encoding,
+                            format: format.clone(),
+                            optional,
+                            scalar_type,
+                            field_name: ident.to_string(),
+                            span: list.span(),
+                        });
+                    }
+
+                    if attr.path().is_ident("gsi") || attr.path().is_ident("lsi") {
+                        let index_name = name.ok_or_else(|| {
+                            Error::new_spanned(attr, "index field must name its index")
+                        })?;
+                        let key = match (attr.path().is_ident("gsi"), index_key.as_deref()) {
+                            (true, Some("pk")) => IndexKey::Pk,
+                            (true, Some("sk")) | (false, None | Some("sk")) => IndexKey::Sk,
+                            (true, _) => {
+                                return Err(Error::new_spanned(
+                                    list,
+                                    "gsi field must set key = \"pk\" or key = \"sk\"",
+                                ));
+                            }
+                            (false, _) => {
+                                return Err(Error::new_spanned(
+                                    list,
+                                    "lsi fields can only contribute to the index sk",
+                                ));
+                            }
+                        };
+                        index_defs.push(RawIndexFieldDef {
                             field_name: ident.to_string(),
+                            index_name,
+                            key,
+                            prefix,
+                            order,
+                            encoding,
+                            format,
+                            optional,
+                            scalar_type,
                             span: list.span(),
                         });
+                    } else if index_key.is_some() {
+                        return Err(Error::new_spanned(
+                            list,
+                            "`key` only applies to #[gsi] and #[lsi] fields",
+                        ));
                     }
                 }
                 // #[pk]
@@ -297,6 +713,10 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
                             field_name: ident.to_string(),
                             prefix: None,
                             order,
+                            encoding: SegmentEncoding::Plain,
+                            format: None,
+                            optional,
+                            scalar_type,
                             span: attr.meta.span(),
                         })
                     }
@@ -306,6 +726,10 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
                             field_name: name.clone().unwrap_or_else(|| ident.to_string()),
                             prefix: None,
                             order,
+                            encoding: SegmentEncoding::Plain,
+                            format: None,
+                            optional,
+                            scalar_type,
                             span: path.span(),
                         })
                     }
@@ -315,10 +739,18 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
                             field_name: ident.to_string(),
                             prefix: None,
                             order: None,
+                            encoding: SegmentEncoding::Plain,
+                            format: None,
+                            optional,
+                            scalar_type,
                             name: "".to_string(),
                             span: path.span(),
                         });
                     }
+
+                    if attr.path().is_ident("gsi") || attr.path().is_ident("lsi") {
+                        return Err(Error::new_spanned(path, "index field must name its index"));
+                    }
                 }
                 _ => {}
             }
@@ -337,6 +769,16 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
                 "Cannot have more than 1 #[sk] per field",
             ));
         }
+        let unique_index_keys: HashSet<(&str, IndexKey)> = index_defs
+            .iter()
+            .map(|index| (index.index_name.as_str(), index.key))
+            .collect();
+        if unique_index_keys.len() < index_defs.len() {
+            return Err(Error::new_spanned(
+                field,
+                "Cannot assign to the same index key more than once per field",
+            ));
+        }
         if !nk_defs.is_empty() {
             let unique_nk_names: HashSet<&str> =
                 nk_defs.iter().map(|nk| nk.name.as_str()).collect();
@@ -348,6 +790,22 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
             }
         }
 
+        // A field is written once per item, so every key it feeds must agree on
+        // its format
+        let formats: HashSet<Option<&str>> = pk_defs
+            .iter()
+            .map(|pk| pk.format.as_deref())
+            .chain(sk_defs.iter().map(|sk| sk.format.as_deref()))
+            .chain(nk_defs.iter().map(|nk| nk.format.as_deref()))
+            .chain(index_defs.iter().map(|index| index.format.as_deref()))
+            .collect();
+        if formats.len() > 1 {
+            return Err(Error::new_spanned(
+                field,
+                "Every key attribute on a field must use the same `format`",
+            ));
+        }
+
         // If multiple pks are defined, check if all of them have order
 
         for pk_def in pk_defs {
@@ -359,7 +817,205 @@ fn parse_struct_fields(input: &DeriveInput) -> Result<Vec<RawStructFieldDefs>, s
         for nk_def in nk_defs {
             all_field_defs.push(RawStructFieldDefs::Nk(nk_def));
         }
+        for index_def in index_defs {
+            all_field_defs.push(RawStructFieldDefs::Index(index_def));
+        }
     }
 
     Ok(all_field_defs)
 }
+
+/// Picks the encoding for `pad = N` or `sortable`. Sortable integers and floats
+/// need different encodings, so the choice follows the field's type.
+fn segment_encoding(
+    field: &syn::Field,
+    pad: Option<usize>,
+    sortable: bool,
+    list: &syn::MetaList,
+) -> Result<SegmentEncoding, syn::Error> {
+    let ty = option_inner(&field.ty).unwrap_or(&field.ty);
+    let type_name = match ty {
+        syn::Type::Path(type_path) => type_path.path.get_ident().map(|i| i.to_string()),
+        _ => None,
+    };
+    match (pad, sortable) {
+        (Some(_), true) => Err(Error::new_spanned(
+            list,
+            "`pad` and `sortable` cannot be combined",
+        )),
+        // Leading zeros are trimmed on the way back, and a minus sign would
+        // break the order, so only unsigned integers are padded
+        (Some(width), false) => match type_name.as_deref() {
+            Some("u8" | "u16" | "u32" | "u64" | "u128" | "usize") => {
+                Ok(SegmentEncoding::Pad(width))
+            }
+            _ => Err(Error::new_spanned(
+                ty,
+                "`pad` needs an unsigned integer field",
+            )),
+        },
+        (None, false) => Ok(SegmentEncoding::Plain),
+        (None, true) => match type_name.as_deref() {
+            Some(
+                "i8" | "i16" | "i32" | "i64" | "isize" | "u8" | "u16" | "u32" | "u64" | "usize",
+            ) => Ok(SegmentEncoding::SortableInt),
+            Some("f32" | "f64") => Ok(SegmentEncoding::SortableFloat),
+            _ => Err(Error::new_spanned(
+                ty,
+                "`sortable` needs an integer (up to 64 bits) or float field",
+            )),
+        },
+    }
+}
+
+/// Writes a sample value of the field's date or time type with `format` and
+/// reads it back, through the same [`FormattedSegment`] code as at runtime, so
+/// that a typo, a specifier the type has no value for, or a format that drops
+/// part of the value fails the build rather than the first write or read.
+///
+/// A type the name does not give away, such as an alias, must round-trip as
+/// one of the supported types.
+///
+/// [`FormattedSegment`]: entity_core::FormattedSegment
+fn check_format(ty: &syn::Type, format: &syn::LitStr) -> Result<(), syn::Error> {
+    let ty = option_inner(ty).unwrap_or(ty);
+    let value = format.value();
+    let checked = match DateTimeType::of(ty) {
+        Some(date_time) => date_time.round_trip(&value),
+        None if DateTimeType::ALL
+            .iter()
+            .any(|date_time| date_time.round_trip(&value).is_ok()) =>
+        {
+            Ok(())
+        }
+        None => Err("no supported date or time type can be written and read back with it".into()),
+    };
+    checked
+        .map_err(|message| Error::new_spanned(format, format!("invalid segment format: {message}")))
+}
+
+/// The date and time types `FormattedSegment` is implemented for.
+#[derive(Clone, Copy)]
+enum DateTimeType {
+    NaiveDate,
+    NaiveDateTime,
+    DateTimeUtc,
+    DateTimeFixedOffset,
+    Date,
+    PrimitiveDateTime,
+    OffsetDateTime,
+}
+
+impl DateTimeType {
+    const ALL: [DateTimeType; 7] = [
+        DateTimeType::NaiveDate,
+        DateTimeType::NaiveDateTime,
+        DateTimeType::DateTimeUtc,
+        DateTimeType::DateTimeFixedOffset,
+        DateTimeType::Date,
+        DateTimeType::PrimitiveDateTime,
+        DateTimeType::OffsetDateTime,
+    ];
+
+    /// By the last segment of the type's path, and the time zone of a `DateTime`.
+    fn of(ty: &syn::Type) -> Option<Self> {
+        let syn::Type::Path(type_path) = ty else {
+            return None;
+        };
+        let last = type_path.path.segments.last()?;
+        match last.ident.to_string().as_str() {
+            "NaiveDate" => Some(DateTimeType::NaiveDate),
+            "NaiveDateTime" => Some(DateTimeType::NaiveDateTime),
+            "DateTime" => {
+                let syn::PathArguments::AngleBracketed(args) = &last.arguments else {
+                    return None;
+                };
+                let Some(syn::GenericArgument::Type(syn::Type::Path(zone))) = args.args.first()
+                else {
+                    return None;
+                };
+                match zone.path.segments.last()?.ident.to_string().as_str() {
+                    "Utc" => Some(DateTimeType::DateTimeUtc),
+                    "FixedOffset" => Some(DateTimeType::DateTimeFixedOffset),
+                    _ => None,
+                }
+            }
+            "Date" => Some(DateTimeType::Date),
+            "PrimitiveDateTime" => Some(DateTimeType::PrimitiveDateTime),
+            "OffsetDateTime" => Some(DateTimeType::OffsetDateTime),
+            _ => None,
+        }
+    }
+
+    /// Writes 2024-03-09 13:05:07, in UTC+2 for a fixed offset, and reads it back.
+    fn round_trip(self, format: &str) -> Result<(), String> {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 3, 9).expect("valid date");
+        let datetime = date.and_hms_opt(13, 5, 7).expect("valid time");
+        let offset = chrono::FixedOffset::east_opt(2 * 3600).expect("valid offset");
+        let time_date =
+            time::Date::from_calendar_date(2024, time::Month::March, 9).expect("valid date");
+        let time_datetime = time::PrimitiveDateTime::new(
+            time_date,
+            time::Time::from_hms(13, 5, 7).expect("valid time"),
+        );
+        match self {
+            DateTimeType::NaiveDate => round_trip(date, format),
+            DateTimeType::NaiveDateTime => round_trip(datetime, format),
+            DateTimeType::DateTimeUtc => round_trip(datetime.and_utc(), format),
+            DateTimeType::DateTimeFixedOffset => round_trip(
+                datetime
+                    .and_local_timezone(offset)
+                    .single()
+                    .expect("a fixed offset is never ambiguous"),
+                format,
+            ),
+            DateTimeType::Date => round_trip(time_date, format),
+            DateTimeType::PrimitiveDateTime => round_trip(time_datetime, format),
+            DateTimeType::OffsetDateTime => round_trip(time_datetime.assume_utc(), format),
+        }
+    }
+}
+
+fn round_trip<T>(sample: T, format: &str) -> Result<(), String>
+where
+    T: entity_core::FormattedSegment + PartialEq,
+{
+    let written = sample.format_segment(format)?;
+    match T::parse_segment(&written, format) {
+        Ok(read) if read == sample => Ok(()),
+        Ok(_) => Err(format!("`{written}` reads back as a different value")),
+        Err(err) => Err(format!("`{written}` cannot be read back: {err}")),
+    }
+}
+
+/// How `serialize_attribute` writes the field, which a bare key keeps.
+fn scalar_type(ty: &syn::Type) -> ScalarType {
+    let ty = option_inner(ty).unwrap_or(ty);
+    let is_float = matches!(ty, syn::Type::Path(type_path)
+        if type_path.path.is_ident("f32") || type_path.path.is_ident("f64"));
+    if codegen::is_integer(ty) || is_float {
+        ScalarType::N
+    } else if codegen::is_bytes(ty) {
+        ScalarType::B
+    } else {
+        ScalarType::S
+    }
+}
+
+/// The `T` of an `Option<T>` field. Such fields make the attributes they feed sparse.
+pub(crate) fn option_inner(ty: &syn::Type) -> Option<&syn::Type> {
+    let syn::Type::Path(type_path) = ty else {
+        return None;
+    };
+    let last = type_path.path.segments.last()?;
+    if last.ident != "Option" {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &last.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        syn::GenericArgument::Type(inner) => Some(inner),
+        _ => None,
+    }
+}
@@ -1,16 +1,22 @@
 use crate::parser::{
-    RawFieldDef, RawNkFieldDef, RawNkStructDef, RawPkFieldDef, RawPkStructDef, RawSkFieldDef,
-    RawSkStructDef, RawStructFieldDefs,
+    IndexKey, RawEntityOptions, RawIndexFieldDef, RawIndexStructDef, RawNkFieldDef, RawNkStructDef,
+    RawPkFieldDef, RawPkStructDef, RawSkFieldDef, RawSkStructDef, RawStructFieldDefs,
+    VariantEntity,
 };
-use entity_core::{AttributeValue, CompositeAttributeValue, KeyDef, SchemaV2, Segment};
+use entity_core::{
+    AttributeValue, CompositeAttributeValue, IndexDef, IndexKind, KeyDef, SchemaV2, Segment,
+};
+use proc_macro2::Span;
 use std::collections::HashMap;
-use syn::Error;
+use syn::Ident;
 
 pub fn build_schema(
     pk_struct_def: Option<RawPkStructDef>,
     sk_struct_def: Option<RawSkStructDef>,
     nk_struct_defs: Vec<RawNkStructDef>,
+    index_struct_defs: Vec<RawIndexStructDef>,
     all_field_defs: Vec<RawStructFieldDefs>,
+    options: RawEntityOptions,
 ) -> Result<SchemaV2, syn::Error> {
     let pk_field_defs: Vec<&RawPkFieldDef> = all_field_defs
         .iter()
@@ -93,6 +99,25 @@ pub fn build_schema(
         }
     }
 
+    // Every item needs its table keys, so they cannot be sparse
+    let optional_table_keys: Vec<Span> = pk_field_defs
+        .iter()
+        .filter(|pk| pk.optional)
+        .map(|pk| pk.span)
+        .chain(
+            sk_field_defs
+                .iter()
+                .filter(|sk| sk.optional)
+                .map(|sk| sk.span),
+        )
+        .collect();
+    if let Some(span) = optional_table_keys.first() {
+        return Err(syn::Error::new(
+            *span,
+            "Table keys cannot be built from `Option` fields. Use an nk or index key for sparse attributes.",
+        ));
+    }
+
     //
     // ─── BUILD PK ────────────────────────────────────────────────────────────────
     //
@@ -104,6 +129,10 @@ pub fn build_schema(
                 field_name: name,
                 prefix,
                 order,
+                encoding,
+                format,
+                optional,
+                scalar_type,
                 ..
             } = &pk_field_def;
             pk_segments.push((
@@ -111,6 +140,10 @@ pub fn build_schema(
                 Segment {
                     struct_field_name: name.clone(),
                     prefix: prefix.clone(),
+                    encoding: *encoding,
+                    format: format.clone(),
+                    optional: *optional,
+                    scalar_type: *scalar_type,
                 },
             ));
         }
@@ -148,11 +181,15 @@ pub fn build_schema(
         KeyDef {
             attribute_name: pk_def.field_name.clone(),
             attribute_value: CompositeAttributeValue {
-                prefix: pk_def.prefix.clone(),
+                prefix: None,
                 suffix: None,
                 segments: vec![Segment {
                     struct_field_name: pk_def.field_name.clone(),
                     prefix: pk_def.prefix.clone(),
+                    encoding: pk_def.encoding,
+                    format: pk_def.format.clone(),
+                    optional: pk_def.optional,
+                    scalar_type: pk_def.scalar_type,
                 }],
             },
         }
@@ -211,6 +248,10 @@ pub fn build_schema(
                     prefix,
                     order,
                     field_name: name,
+                    encoding,
+                    format,
+                    optional,
+                    scalar_type,
                     ..
                 }) = &field_info
                 {
@@ -219,6 +260,10 @@ pub fn build_schema(
                         Segment {
                             struct_field_name: name.clone(),
                             prefix: prefix.clone(),
+                            encoding: *encoding,
+                            format: format.clone(),
+                            optional: *optional,
+                            scalar_type: *scalar_type,
                         },
                     ));
                 }
@@ -255,20 +300,21 @@ pub fn build_schema(
         }
         let sk_def = yo.pop();
 
-        match sk_def {
-            None => None,
-            Some(sk_def) => Some(KeyDef {
-                attribute_name: sk_def.field_name.clone(),
-                attribute_value: AttributeValue::Composite(CompositeAttributeValue {
+        sk_def.map(|sk_def| KeyDef {
+            attribute_name: sk_def.field_name.clone(),
+            attribute_value: AttributeValue::Composite(CompositeAttributeValue {
+                prefix: None,
+                suffix: None,
+                segments: vec![Segment {
+                    struct_field_name: sk_def.field_name.clone(),
                     prefix: sk_def.prefix.clone(),
-                    suffix: None,
-                    segments: vec![Segment {
-                        struct_field_name: sk_def.field_name.clone(),
-                        prefix: sk_def.prefix.clone(),
-                    }],
-                }),
+                    encoding: sk_def.encoding,
+                    format: sk_def.format.clone(),
+                    optional: sk_def.optional,
+                    scalar_type: sk_def.scalar_type,
+                }],
             }),
-        }
+        })
     };
 
     //
@@ -302,12 +348,18 @@ pub fn build_schema(
     }
 
     // add field-level NKs
+    let mut nk_segments: HashMap<String, Vec<(Option<usize>, Segment)>> = HashMap::new();
     for nk_field_def in &nk_field_defs {
         let RawNkFieldDef {
             field_name,
             name: tied_to,
             prefix,
-            ..
+            order,
+            encoding,
+            format,
+            optional,
+            scalar_type,
+            span,
         } = &nk_field_def;
 
         // Hack
@@ -317,38 +369,422 @@ pub fn build_schema(
             tied_to.clone()
         };
 
-        nk_map
-            .entry(look_up_key)
-            .and_modify(|key_def| {
-                if let AttributeValue::Composite(CompositeAttributeValue { segments, .. }) =
-                    &mut key_def.attribute_value
-                {
-                    segments.push(Segment {
-                        struct_field_name: field_name.clone(),
-                        prefix: prefix.clone(),
-                    });
-                }
-            })
-            .or_insert(KeyDef {
-                attribute_name: field_name.clone(),
-                attribute_value: AttributeValue::Composite(CompositeAttributeValue {
-                    prefix: None,
-                    suffix: None,
-                    segments: vec![Segment {
-                        struct_field_name: field_name.clone(),
-                        prefix: prefix.clone(),
-                    }],
-                }),
-            });
+        if let Some(KeyDef {
+            attribute_value: AttributeValue::Static(_),
+            ..
+        }) = nk_map.get(&look_up_key)
+        {
+            return Err(syn::Error::new(
+                *span,
+                format!("nk `{look_up_key}` has a static value, fields cannot contribute to it"),
+            ));
+        }
+
+        nk_map.entry(look_up_key.clone()).or_insert(KeyDef {
+            attribute_name: field_name.clone(),
+            attribute_value: AttributeValue::Composite(CompositeAttributeValue {
+                prefix: None,
+                suffix: None,
+                segments: vec![],
+            }),
+        });
+        nk_segments.entry(look_up_key).or_default().push((
+            *order,
+            Segment {
+                struct_field_name: field_name.clone(),
+                prefix: prefix.clone(),
+                encoding: *encoding,
+                format: format.clone(),
+                optional: *optional,
+                scalar_type: *scalar_type,
+            },
+        ));
     }
 
     // sort NK segments by order and flatten
+    for (look_up_key, mut segments) in nk_segments {
+        segments.sort_by_key(|(ord, _)| *ord);
+        if let Some(KeyDef {
+            attribute_value: AttributeValue::Composite(composite),
+            ..
+        }) = nk_map.get_mut(&look_up_key)
+        {
+            composite.segments = segments.into_iter().map(|(_, seg)| seg).collect();
+        }
+    }
 
-    let non_key_defs: Vec<KeyDef<AttributeValue>> = nk_map.into_values().collect();
+    let mut non_key_defs: Vec<KeyDef<AttributeValue>> = nk_map.into_values().collect();
+
+    //
+    // ─── BUILD INDEXES ───────────────────────────────────────────────────────────
+    //
+    let index_field_defs: Vec<&RawIndexFieldDef> = all_field_defs
+        .iter()
+        .filter_map(|field| {
+            if let RawStructFieldDefs::Index(index) = &field {
+                Some(index)
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    for index_field_def in &index_field_defs {
+        let Some(index) = index_struct_defs
+            .iter()
+            .find(|index| index.index_name == index_field_def.index_name)
+        else {
+            return Err(syn::Error::new(
+                index_field_def.span,
+                format!(
+                    "No struct-level #[gsi] or #[lsi] named `{}`",
+                    index_field_def.index_name
+                ),
+            ));
+        };
+        if index.kind == IndexKind::Local && index_field_def.key == IndexKey::Pk {
+            return Err(syn::Error::new(
+                index_field_def.span,
+                "A local index shares the table's pk, fields cannot contribute to it",
+            ));
+        }
+    }
+
+    let mut index_defs = vec![];
+    for index in index_struct_defs {
+        let contributions = |key: IndexKey| -> Vec<&RawIndexFieldDef> {
+            index_field_defs
+                .iter()
+                .filter(|field| field.index_name == index.index_name && field.key == key)
+                .copied()
+                .collect()
+        };
+
+        let index_partition_key_def = match (&index.kind, &index.pk) {
+            (IndexKind::Global, Some(name)) => {
+                let attribute_value = index_key_attribute(
+                    name,
+                    contributions(IndexKey::Pk),
+                    &partition_key_def,
+                    &sort_key_def,
+                    &mut non_key_defs,
+                    index.span,
+                )?;
+                let AttributeValue::Composite(attribute_value) = attribute_value else {
+                    return Err(syn::Error::new(
+                        index.span,
+                        format!("Index pk `{name}` has a static value"),
+                    ));
+                };
+                KeyDef {
+                    attribute_name: name.clone(),
+                    attribute_value,
+                }
+            }
+            _ => partition_key_def.clone(),
+        };
+
+        let index_sort_key_def = match &index.sk {
+            Some(name) => Some(KeyDef {
+                attribute_name: name.clone(),
+                attribute_value: index_key_attribute(
+                    name,
+                    contributions(IndexKey::Sk),
+                    &partition_key_def,
+                    &sort_key_def,
+                    &mut non_key_defs,
+                    index.span,
+                )?,
+            }),
+            None => {
+                if let Some(field) = contributions(IndexKey::Sk).first() {
+                    return Err(syn::Error::new(
+                        field.span,
+                        format!("Index `{}` has no sk", index.index_name),
+                    ));
+                }
+                None
+            }
+        };
+
+        index_defs.push(IndexDef {
+            index_name: index.index_name,
+            kind: index.kind,
+            partition_key_def: index_partition_key_def,
+            sort_key_def: index_sort_key_def,
+        });
+    }
 
     Ok(SchemaV2 {
         partition_key_def,
         sort_key_def,
         non_key_defs,
+        index_defs,
+        delimiter: options.delimiter.unwrap_or(entity_core::DEFAULT_DELIMITER),
+        version_attribute: None,
+        table_name: options.table,
     })
 }
+
+/// Finds or builds the attribute an index key is stored in. An attribute that
+/// already exists, such as a table key, an nk or another index's key, is reused
+/// as is. Otherwise it is composed from the fields that contribute to this
+/// index key and added to the non-key attributes.
+fn index_key_attribute(
+    name: &str,
+    contributions: Vec<&RawIndexFieldDef>,
+    partition_key_def: &KeyDef<CompositeAttributeValue>,
+    sort_key_def: &Option<KeyDef<AttributeValue>>,
+    non_key_defs: &mut Vec<KeyDef<AttributeValue>>,
+    span: proc_macro2::Span,
+) -> Result<AttributeValue, syn::Error> {
+    let existing = if partition_key_def.attribute_name == name {
+        Some(AttributeValue::Composite(
+            partition_key_def.attribute_value.clone(),
+        ))
+    } else {
+        sort_key_def
+            .iter()
+            .chain(non_key_defs.iter())
+            .find(|key_def| key_def.attribute_name == name)
+            .map(|key_def| key_def.attribute_value.clone())
+    };
+
+    match existing {
+        Some(attribute_value) => {
+            if contributions.is_empty() {
+                return Ok(attribute_value);
+            }
+            let mut diagnostic = syn::Error::new(
+                span,
+                format!(
+                    "`{name}` is already defined, fields cannot contribute to it through an index"
+                ),
+            );
+            for field in contributions {
+                diagnostic.combine(syn::Error::new(field.span, "This field contributes to it."));
+            }
+            Err(diagnostic)
+        }
+        None => {
+            if contributions.is_empty() {
+                return Err(syn::Error::new(
+                    span,
+                    format!("No fields contribute to index key `{name}`"),
+                ));
+            }
+            if contributions.len() > 1 {
+                let missing_order: Vec<_> = contributions
+                    .iter()
+                    .filter(|field| field.order.is_none())
+                    .map(|field| field.span)
+                    .collect();
+                if let Some(first) = missing_order.first() {
+                    return Err(syn::Error::new(
+                        *first,
+                        format!(
+                            "Multiple fields contribute to `{name}`, so each needs an `order` attribute."
+                        ),
+                    ));
+                }
+            }
+
+            let mut segments: Vec<(Option<usize>, Segment)> = contributions
+                .iter()
+                .map(|field| {
+                    (
+                        field.order,
+                        Segment {
+                            struct_field_name: field.field_name.clone(),
+                            prefix: field.prefix.clone(),
+                            encoding: field.encoding,
+                            format: field.format.clone(),
+                            optional: field.optional,
+                            scalar_type: field.scalar_type,
+                        },
+                    )
+                })
+                .collect();
+            segments.sort_by_key(|(ord, _)| *ord);
+
+            let attribute_value = AttributeValue::Composite(CompositeAttributeValue {
+                prefix: None,
+                suffix: None,
+                segments: segments.into_iter().map(|(_, seg)| seg).collect(),
+            });
+            non_key_defs.push(KeyDef {
+                attribute_name: name.to_string(),
+                attribute_value: attribute_value.clone(),
+            });
+            Ok(attribute_value)
+        }
+    }
+}
+
+/// Stores the variant tag of an enum entity as a static attribute, so that
+/// `from_item` knows which variant to decode.
+pub fn add_discriminator(
+    schema: &mut SchemaV2,
+    discriminator: &str,
+    tag: &str,
+    span: Span,
+) -> Result<(), syn::Error> {
+    let is_taken = schema
+        .composite_defs()
+        .map(|(name, _)| name)
+        .chain(schema.static_defs().map(|(name, _)| name))
+        .any(|name| name == discriminator);
+    if is_taken {
+        return Err(syn::Error::new(
+            span,
+            format!("`{discriminator}` holds the variant tag and cannot be a key attribute"),
+        ));
+    }
+    schema.non_key_defs.push(KeyDef {
+        attribute_name: discriminator.to_string(),
+        attribute_value: AttributeValue::Static(tag.to_string()),
+    });
+    Ok(())
+}
+
+/// The schema an enum reports from `get_schema`, holding what its variants
+/// share. Every variant must compose the partition key the same way and name
+/// the same sort key attribute. Where variants compose a sort key or index key
+/// differently, only its attribute name is kept and its segments are left empty.
+pub fn collection_schema(variants: &[VariantEntity], span: Span) -> Result<SchemaV2, syn::Error> {
+    let Some((first, rest)) = variants.split_first() else {
+        return Err(syn::Error::new(
+            span,
+            "Dynodmize enums need at least one variant",
+        ));
+    };
+
+    let mut schema = SchemaV2 {
+        partition_key_def: first.schema.partition_key_def.clone(),
+        sort_key_def: first.schema.sort_key_def.clone(),
+        non_key_defs: vec![],
+        index_defs: first.schema.index_defs.clone(),
+        delimiter: first.schema.delimiter,
+        version_attribute: None,
+        table_name: first.schema.table_name.clone(),
+    };
+
+    for variant in rest {
+        if variant.schema.partition_key_def != schema.partition_key_def {
+            return Err(syn::Error::new(
+                variant.ident.span(),
+                format!(
+                    "Variants share one partition, so this pk must match `{}`'s",
+                    first.ident
+                ),
+            ));
+        }
+
+        schema.sort_key_def = match (&schema.sort_key_def, &variant.schema.sort_key_def) {
+            (None, None) => None,
+            (Some(shared), Some(own)) if shared.attribute_name == own.attribute_name => {
+                Some(shared_key_def(shared, own))
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    variant.ident.span(),
+                    format!(
+                        "Variants must name the same sk attribute as `{}`",
+                        first.ident
+                    ),
+                ));
+            }
+        };
+
+        for own in &variant.schema.index_defs {
+            let Some(shared) = schema
+                .index_defs
+                .iter_mut()
+                .find(|index| index.index_name == own.index_name)
+            else {
+                schema.index_defs.push(own.clone());
+                continue;
+            };
+            let same_attributes = shared.kind == own.kind
+                && shared.partition_key_def.attribute_name == own.partition_key_def.attribute_name
+                && shared.sort_key_def.as_ref().map(|sk| &sk.attribute_name)
+                    == own.sort_key_def.as_ref().map(|sk| &sk.attribute_name);
+            if !same_attributes {
+                return Err(syn::Error::new(
+                    variant.ident.span(),
+                    format!(
+                        "Index `{}` is declared with different key attributes in another variant",
+                        own.index_name
+                    ),
+                ));
+            }
+            if shared.partition_key_def != own.partition_key_def {
+                shared.partition_key_def.attribute_value = CompositeAttributeValue {
+                    segments: vec![],
+                    prefix: None,
+                    suffix: None,
+                };
+            }
+            if let (Some(shared_sk), Some(own_sk)) = (&shared.sort_key_def, &own.sort_key_def) {
+                shared.sort_key_def = Some(shared_key_def(shared_sk, own_sk));
+            }
+        }
+    }
+
+    Ok(schema)
+}
+
+fn shared_key_def(
+    shared: &KeyDef<AttributeValue>,
+    own: &KeyDef<AttributeValue>,
+) -> KeyDef<AttributeValue> {
+    if shared == own {
+        return shared.clone();
+    }
+    KeyDef {
+        attribute_name: shared.attribute_name.clone(),
+        attribute_value: AttributeValue::Composite(CompositeAttributeValue {
+            segments: vec![],
+            prefix: None,
+            suffix: None,
+        }),
+    }
+}
+
+/// A derived attribute may only share its name with a struct field when that
+/// field is its sole segment. The key-encoded value then replaces the field's
+/// plain serialization in the item, and `from_item` reads the field back from
+/// the key. Any other overlap would silently overwrite the field, so it is
+/// rejected.
+pub fn validate_attribute_names(schema: &SchemaV2, fields: &[&Ident]) -> Result<(), syn::Error> {
+    let pk = &schema.partition_key_def;
+    let derived = std::iter::once((&pk.attribute_name, Some(&pk.attribute_value))).chain(
+        schema
+            .sort_key_def
+            .iter()
+            .chain(&schema.non_key_defs)
+            .map(|key_def| match &key_def.attribute_value {
+                AttributeValue::Composite(composite) => (&key_def.attribute_name, Some(composite)),
+                AttributeValue::Static(_) => (&key_def.attribute_name, None),
+            }),
+    );
+
+    for (attribute_name, composite) in derived {
+        let Some(field) = fields.iter().find(|field| **field == attribute_name) else {
+            continue;
+        };
+        let is_own_key = composite.is_some_and(|composite| {
+            matches!(&composite.segments[..], [segment] if segment.struct_field_name == *attribute_name)
+        });
+        if !is_own_key {
+            return Err(syn::Error::new(
+                field.span(),
+                format!(
+                    "Attribute `{attribute_name}` is derived from other fields and would overwrite this field. Rename the key attribute."
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
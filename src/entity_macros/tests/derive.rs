@@ -0,0 +1,13 @@
+#[test]
+fn compile_fail() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
+
+/// Derived code only names `entity_core`, so these build without the SDK or
+/// `serde_json` as dependencies of their own.
+#[test]
+fn compile_pass() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/pass/*.rs");
+}
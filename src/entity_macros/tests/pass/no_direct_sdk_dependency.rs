@@ -0,0 +1,47 @@
+use entity_core::{Entity2, PrimaryKey, TableConfig};
+use entity_macros::{Dynodmize, based_on};
+use serde::Serialize;
+
+#[derive(Dynodmize, Serialize)]
+#[dynodmize(table = "social")]
+#[pk(name = "pk")]
+#[sk(name = "sk")]
+pub struct Post {
+    #[pk(prefix = "u")]
+    pub user_id: u32,
+    #[sk(order = 0, prefix = "t")]
+    pub topic: String,
+    #[sk(order = 1, prefix = "p")]
+    pub post_id: u32,
+    pub likes: u64,
+}
+
+#[derive(Dynodmize, Serialize)]
+#[pk(name = "pk")]
+pub enum Partition {
+    #[sk(name = "sk", value = "profile")]
+    Profile {
+        #[pk(prefix = "u")]
+        user_id: u32,
+    },
+}
+
+#[based_on(Post, tables = tables)]
+pub struct PostRepo {
+    pub tables: TableConfig,
+}
+
+fn main() {
+    let post = Post {
+        user_id: 1,
+        topic: "rust".to_string(),
+        post_id: 2,
+        likes: 0,
+    };
+    let item = post.to_item().unwrap();
+    assert_eq!(item["sk"], "t#rust#p#2");
+    assert!(Post::from_item(&item).is_ok());
+    assert!(post.key().to_key().is_ok());
+    assert!(Partition::Profile { user_id: 1 }.to_attribute_map().is_ok());
+    assert_eq!(Post::sk_prefix().topic("rust".to_string()).to_string(), "t#rust#");
+}
@@ -0,0 +1,12 @@
+use chrono::NaiveDateTime;
+use entity_macros::Dynodmize;
+
+// Reads back, but without the seconds the field was written with
+#[derive(Dynodmize)]
+#[pk(name = "pk")]
+struct Reading {
+    #[pk(format = "%Y-%m-%dT%H:%M")]
+    taken_at: NaiveDateTime,
+}
+
+fn main() {}
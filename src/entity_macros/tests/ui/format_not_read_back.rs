@@ -0,0 +1,12 @@
+use chrono::NaiveDate;
+use entity_macros::Dynodmize;
+
+// Writes fine, but a year alone is not a date
+#[derive(Dynodmize)]
+#[pk(name = "pk")]
+struct Reminder {
+    #[pk(format = "%Y")]
+    due_on: NaiveDate,
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+use entity_macros::Dynodmize;
+
+// The derive cannot tell what `Day` is, so the format has to work for a
+// supported type
+type Day = chrono::NaiveDate;
+
+#[derive(Dynodmize)]
+#[pk(name = "pk")]
+struct Reminder {
+    #[pk(format = "%Y")]
+    due_on: Day,
+}
+
+fn main() {}
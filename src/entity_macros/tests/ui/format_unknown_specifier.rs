@@ -0,0 +1,11 @@
+use chrono::NaiveDate;
+use entity_macros::Dynodmize;
+
+#[derive(Dynodmize)]
+#[pk(name = "pk")]
+struct Reminder {
+    #[pk(format = "%Y-%m-%Q")]
+    due_on: NaiveDate,
+}
+
+fn main() {}
@@ -1,9 +1,10 @@
-use aws_sdk_dynamodb::Client;
+use entity_core::TableConfig;
 use entity_macros::{based_on, Dynodmize, EntityModel};
 use serde::Serialize;
 
 
-#[derive(Dynodmize)]
+#[derive(Dynodmize, Serialize)]
+#[dynodmize(delimiter = "|")]
 #[pk(name = "mypk")]
 #[sk(name = "combined_sk")]
 #[nk(name = "type", value = "dynamo")]
@@ -27,8 +28,8 @@ pub struct ComplaintComments {
 }
 
 
-#[derive(Dynodmize)]
-#[pk(name = "last_name")]
+#[derive(Dynodmize, Serialize)]
+#[pk(name = "pk")]
 #[sk(name = "dd")]
 #[nk(name = "type", value = "dynamo")]
 pub struct User {
@@ -51,20 +52,30 @@ pub struct User {
 }
 
 // ── ENTITY ────────────────────────────────────────
-#[derive(Debug, Default, EntityModel, Serialize)]
+#[derive(Debug, Default, Dynodmize, EntityModel, Serialize)]
+#[dynodmize(table = "test")]
+#[sk(name = "dd")]
 pub struct MyEntity2 {
+    #[pk]
     #[partition_key]
     pub pk: String,
 
+    #[sk(order = 0, prefix = "ATTRIBUTE2_HELLO")]
     #[sort(key = "dd")]
     pub attribute2_hello: bool,
 
+    #[sk(order = 1, prefix = "ATTRIBUTE3")]
     #[sort(key = "dd")]
     pub attribute3: String,
 
     pub attribute4: bool,
+
+    #[version]
+    pub version: u64,
 }
 
 // ── REPO ──────────────────────────────────────────
-#[based_on(MyEntity2)]
-pub struct Entity2Repo;
+#[based_on(MyEntity2, tables = tables)]
+pub struct Entity2Repo {
+    pub tables: TableConfig,
+}
@@ -2,35 +2,60 @@ mod entity2tings;
 mod recurring_payments;
 mod social_network;
 
-use crate::entity2tings::{ComplaintComments, Entity2Repo, MyEntity2, MyEntity2Setters};
+use crate::entity2tings::{
+    ComplaintComments, Entity2Repo, MyEntity2, MyEntity2Fields, MyEntity2Setters, User,
+};
 use crate::recurring_payments::AccountReceiptSubscription;
-use crate::social_network::UserCount;
+use crate::social_network::{
+    UserCount, UserCountCounters, UserCountKey, UserFollower, UserPartition, UserProfile,
+};
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_dynamodb::Client;
 use aws_sdk_dynamodb::config::{BehaviorVersion, Region};
+use chrono::NaiveDate;
 use entity_core::Entity2;
 use entity_core::*;
 use entity_macros::Dynodmize;
+use futures::StreamExt;
+use serde::Serialize;
+use std::pin::pin;
 // #[tokio::main]
 // async fn main() {
 //     main2().await
 // }
 
-#[derive(Dynodmize)]
+#[derive(Debug, Dynodmize, Serialize)]
+#[dynodmize(table = "social")]
 #[pk(name = "pk")]
 #[sk(name = "sk")]
+#[lsi(name = "FollowingIndex", sk = "lsi1sk")]
 struct Timeline {
     #[pk(prefix = "u")]
     user_id: u32,
 
-    #[sk(prefix = "p", order = 0)]
+    #[sk(prefix = "p", order = 0, pad = 10)]
     post_id: u32,
 
     #[sk(prefix = "u", order = 1)]
+    #[lsi(name = "FollowingIndex", prefix = "u")]
     following_id: u32,
 }
 
-#[derive(Dynodmize)]
+#[derive(Debug, Dynodmize, Serialize)]
+#[pk(name = "pk")]
+#[sk(name = "sk")]
+struct Reading {
+    #[pk(prefix = "s")]
+    sensor_id: u32,
+
+    #[sk(prefix = "t", order = 0, sortable)]
+    celsius: f64,
+
+    #[sk(prefix = "d", order = 1, sortable)]
+    drift: i64,
+}
+
+#[derive(Debug, Dynodmize, Serialize)]
 struct UserItem {
     #[pk(prefix = "u")]
     username: String,
@@ -47,7 +72,23 @@ fn main() {
         comment_id: 456,
         attribute2: "d".to_string(),
     };
-    println!("{}", serde_json::to_string_pretty(&ent.to_item()).unwrap());
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&ent.to_item().unwrap()).unwrap()
+    );
+
+    let user = User {
+        attribute2: "a2".to_string(),
+        last_name: "Tan".to_string(),
+        first_name: "Remy".to_string(),
+        attribute3: "a3".to_string(),
+        attribute4: "a4".to_string(),
+        attribute5: "a5".to_string(),
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&user.to_item().unwrap()).unwrap()
+    );
 
     let user_count = UserCount {
         user_id: 123,
@@ -57,28 +98,48 @@ fn main() {
     };
     println!(
         "{}",
-        serde_json::to_string_pretty(&user_count.to_item()).unwrap()
+        serde_json::to_string_pretty(&user_count.to_item().unwrap()).unwrap()
+    );
+
+    let user_follower = UserFollower {
+        user_id: 12345,
+        follower_id: 23456,
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&user_follower.to_item().unwrap()).unwrap()
     );
+    // {"pk": S("u#12345#follower"), "sk": S("u#23456")}
+    println!("{:?}", user_follower.key().to_key().unwrap());
 
     let user_item = UserItem {
-        username: "user001".to_string(),
+        username: "user#001".to_string(),
         item_id: 999,
     };
     println!(
         "{}",
-        serde_json::to_string_pretty(&user_item.to_item()).unwrap()
+        serde_json::to_string_pretty(&user_item.to_item().unwrap()).unwrap()
+    );
+    println!(
+        "{:?}",
+        UserItem::from_item(&user_item.to_item().unwrap()).unwrap()
     );
 
     let account_receipt_subscription = AccountReceiptSubscription {
         account_id: 123,
-        last_reminder_date: "2025-09-20".to_string(),
-        next_reminder_date: "2025-09-27".to_string(),
+        last_reminder_date: NaiveDate::from_ymd_opt(2025, 9, 20).unwrap(),
+        next_reminder_date: NaiveDate::from_ymd_opt(2025, 9, 27).unwrap(),
         subscription_id: 987,
         sku: 999,
     };
     println!(
         "{}",
-        serde_json::to_string_pretty(&account_receipt_subscription.to_item()).unwrap()
+        serde_json::to_string_pretty(&account_receipt_subscription.to_item().unwrap()).unwrap()
+    );
+    println!(
+        "{:?}",
+        AccountReceiptSubscription::from_item(&account_receipt_subscription.to_item().unwrap())
+            .unwrap()
     );
 
     let timeline = Timeline {
@@ -88,8 +149,81 @@ fn main() {
     };
     println!(
         "{}",
-        serde_json::to_string_pretty(&timeline.to_item()).unwrap()
+        serde_json::to_string_pretty(&timeline.to_item().unwrap()).unwrap()
+    );
+    println!(
+        "{:?}",
+        Timeline::from_item(&timeline.to_item().unwrap()).unwrap()
+    );
+    println!("{:?}", Timeline::get_schema().index("FollowingIndex"));
+    // p#0000000111#
+    println!("{}", Timeline::sk_prefix().post_id(111));
+
+    // Page tokens only decode against the schema they were issued for
+    let cursor = Cursor::new(timeline.key().to_key().unwrap());
+    let token = cursor.encode_signed(b"secret");
+    println!("{token}");
+    assert_eq!(
+        Cursor::decode_signed(&token, &Timeline::get_schema(), b"secret").unwrap(),
+        cursor
+    );
+    println!(
+        "{}",
+        Cursor::decode(&cursor.encode(), &UserItem::get_schema()).unwrap_err()
+    );
+
+    let reading = Reading {
+        sensor_id: 7,
+        celsius: -12.5,
+        drift: -3,
+    };
+    println!("{}", reading.to_item().unwrap()["sk"]);
+    println!(
+        "{:?}",
+        Reading::from_item(&reading.to_item().unwrap()).unwrap()
+    );
+
+    let user_count_item = user_count.to_attribute_map().unwrap();
+    println!("{:?}", user_count_item);
+    println!(
+        "{}",
+        serde_json::to_string_pretty(
+            &UserCount::from_attribute_map(&user_count_item)
+                .unwrap()
+                .to_item()
+                .unwrap()
+        )
+        .unwrap()
     );
+
+    // Unverified profiles have no `gsi1pk` and stay out of `VerifiedIndex`
+    for verified_on in [NaiveDate::from_ymd_opt(2025, 9, 1), None] {
+        let profile = UserProfile {
+            user_id: 123,
+            verified_on,
+        };
+        let item = profile.to_attribute_map().unwrap();
+        println!("{:?}", item);
+        println!("{:?}", UserProfile::from_attribute_map(&item).unwrap());
+    }
+
+    // One partition, two item types, decoded by their `entity_type`
+    let partition = [
+        UserPartition::Profile {
+            user_id: 123,
+            display_name: "Remy".to_string(),
+        },
+        UserPartition::Post {
+            user_id: 123,
+            post_id: 111,
+            title: "Hello".to_string(),
+        },
+    ];
+    for entity in &partition {
+        let item = entity.to_item().unwrap();
+        println!("{item}");
+        println!("{:?}", UserPartition::from_item(&item).unwrap());
+    }
 }
 
 #[allow(dead_code)]
@@ -97,7 +231,7 @@ async fn main2() {
     let region_provider =
         RegionProviderChain::default_provider().or_else(Region::new("ap-southeast-1"));
 
-    let shared_config = aws_config::defaults(BehaviorVersion::v2025_08_07())
+    let shared_config = aws_config::defaults(BehaviorVersion::latest())
         .region(region_provider)
         .load()
         .await;
@@ -106,7 +240,11 @@ async fn main2() {
     let m = client.list_tables().send().await.unwrap();
     println!("{:?}", m.table_names);
 
-    let repo = Entity2Repo;
+    // Every table name gets the environment's prefix, such as `dev-social`
+    let tables = TableConfig::new().prefix("dev-");
+    let repo = Entity2Repo {
+        tables: tables.clone(),
+    };
 
     // ── CREATE ─────────────────────────────────────
     let entity = MyEntity2 {
@@ -114,22 +252,189 @@ async fn main2() {
         attribute2_hello: true,
         attribute3: "sk_partB".into(),
         attribute4: true,
+        version: 0,
     };
 
     println!("PK: {}", entity.get_partition_key());
     println!("SK: {}", entity.get_sort_key().unwrap());
+    let key = entity.key();
+
+    repo.create(entity, client.clone())
+        .if_not_exists()
+        .send2()
+        .await
+        .unwrap();
+
+    // ── GET ────────────────────────────────────────
+    let fetched = repo
+        .get(key.clone(), client.clone())
+        .send()
+        .await
+        .unwrap()
+        .expect("the entity was just created");
 
-    repo.create(entity, client).send2().await.unwrap();
+    println!("Fetched: {:?}", fetched);
 
     // ── QUERY ──────────────────────────────────────
-    let results = repo.query().where_partition_key("pk_123").send();
+    let results = repo
+        .query(client.clone())
+        .where_partition_key("pk_123")
+        .where_sort_key(SortKeyCondition::begins_with(
+            MyEntity2::sk_prefix().attribute2_hello(true),
+        ))
+        .send()
+        .await
+        .unwrap();
 
     println!("Queried result: {:?}", results);
 
+    // One page at a time, resuming from where the last page stopped
+    let page = repo
+        .query(client.clone())
+        .where_partition_key("pk_123")
+        .limit(10)
+        .send_page()
+        .await
+        .unwrap();
+    println!("First page: {:?}", page.items);
+    if let Some(key) = page.last_evaluated_key {
+        let mut rest = pin!(
+            repo.query(client.clone())
+                .where_partition_key("pk_123")
+                .start_from(key)
+                .into_stream()
+        );
+        while let Some(entity) = rest.next().await {
+            println!("Streamed: {:?}", entity.unwrap());
+        }
+    }
+
     // ── UPDATE ─────────────────────────────────────
-    repo.update()
-        .set_attribute2_hello(true)
+    let updated = repo
+        .update(key.clone(), client.clone())
+        .unwrap()
         .set_attribute4(false)
-        .where_partition_key("pk_123")
-        .send();
+        .condition(MyEntity2Fields::attribute4().eq(true))
+        .expect_version(fetched.version)
+        .send_returning()
+        .await
+        .unwrap();
+
+    println!("Updated: {:?}", updated);
+
+    // ── COUNTERS ───────────────────────────────────
+    let count_key = UserCountKey { user_id: 123 }.to_key().unwrap();
+    let counts = UpdateBuilder::<UserCount>::new(count_key, client.clone())
+        .tables(tables.clone())
+        .increment_followers(1)
+        .decrement_followings(1)
+        .send_returning()
+        .await
+        .unwrap();
+
+    println!("Followers: {}", counts.followers);
+
+    // Only the counter comes back, not the whole item
+    let count_key = UserCountKey { user_id: 123 }.to_key().unwrap();
+    let followers = UpdateBuilder::<UserCount>::new(count_key, client.clone())
+        .tables(tables.clone())
+        .increment_followers(1)
+        .send_returning_followers()
+        .await
+        .unwrap();
+
+    println!("Followers now: {}", followers);
+
+    // ── TRANSACTION ────────────────────────────────
+    // The follower edge and the count change together or not at all
+    let follower = UserFollower {
+        user_id: 123,
+        follower_id: 456,
+    };
+    Transaction::new(client.clone())
+        .put(
+            CreateBuilder::new(follower, client.clone())
+                .tables(tables.clone())
+                .if_not_exists(),
+        )
+        .update(
+            UpdateBuilder::<UserCount>::new(
+                UserCountKey { user_id: 123 }.to_key().unwrap(),
+                client.clone(),
+            )
+            .tables(tables.clone())
+            .increment_followers(1),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // ── BATCH ──────────────────────────────────────
+    // 60 posts and a count of them go out as three chunks of at most 25
+    let posts = (0..60).map(|post_id| Timeline {
+        user_id: 987,
+        post_id,
+        following_id: 2344224,
+    });
+    let count = UserCount {
+        user_id: 987,
+        followers: 0,
+        followings: 1,
+        posts: 60,
+    };
+    let report = BatchWriter::new(client.clone())
+        .tables(tables.clone())
+        .put_all(posts.map(BatchWrite::from).chain([count.into()]))
+        .send()
+        .await
+        .unwrap();
+    assert!(report.is_complete(), "{report:?}");
+
+    // Keys of several entities in one round of reads, duplicates fetched once
+    let timeline_keys = BatchKeys::new((0..60).chain(0..10).map(|post_id| TimelineKey {
+        user_id: 987,
+        post_id,
+        following_id: 2344224,
+    }));
+    let count_keys = BatchKeys::new([UserCountKey { user_id: 987 }]);
+    let results = BatchGetter::new(client.clone())
+        .tables(tables)
+        .get_all(&timeline_keys)
+        .get_all(&count_keys)
+        .send()
+        .await
+        .unwrap();
+    assert!(results.is_complete(), "{results:?}");
+    let timeline = results.in_order(timeline_keys).unwrap();
+    println!(
+        "Read {} of 70 posts",
+        timeline.into_iter().filter_map(Lookup::found).count()
+    );
+    let counts = results.by_key(count_keys).unwrap();
+    match counts.get(&UserCountKey { user_id: 987 }) {
+        Lookup::Found(count) => println!("Posts: {}", count.posts),
+        Lookup::Missing => println!("No count yet"),
+        Lookup::Failed => println!("The count could not be read, try again"),
+    }
+
+    // ── MOVE ───────────────────────────────────────
+    // `attribute3` is part of the sort key, so the item is re-keyed
+    let moved = repo
+        .update(key, client.clone())
+        .unwrap()
+        .set_attribute3("sk_partC".into())
+        .expect_version(updated.version)
+        .send_returning()
+        .await
+        .unwrap();
+
+    println!("Moved: {:?}", moved);
+
+    // ── DELETE ─────────────────────────────────────
+    // Only once the entity is no longer flagged
+    repo.delete(moved.key(), client)
+        .condition(MyEntity2Fields::attribute4().ne(true))
+        .send()
+        .await
+        .unwrap();
 }
@@ -1,24 +1,25 @@
+use chrono::NaiveDate;
 use entity_macros::Dynodmize;
+use serde::Serialize;
 
-#[derive(Dynodmize)]
-#[pk(name = "NextReminderDate")]
-#[sk(name = "LastReminderDate")]
-#[nk(name = "SK")]
-#[nk(name = "PK")]
+#[derive(Debug, Dynodmize, Serialize)]
+#[pk(name = "PK")]
+#[sk(name = "SK")]
+#[gsi(name = "ReminderIndex", pk = "NextReminderDate", sk = "LastReminderDate")]
 pub struct AccountReceiptSubscription {
-    #[pk]
-    pub next_reminder_date: String,
+    #[gsi(name = "ReminderIndex", key = "pk", format = "%Y-%m-%d")]
+    pub next_reminder_date: NaiveDate,
 
-    #[sk]
-    pub last_reminder_date: String,
+    #[gsi(name = "ReminderIndex", key = "sk", format = "%Y-%m-%d")]
+    pub last_reminder_date: NaiveDate,
 
-    #[nk(name = "SK", prefix = "SUB", order = 1)]
+    #[sk(prefix = "SUB", order = 1)]
     pub subscription_id: u32,
 
-    #[nk(name = "SK", prefix = "SKU", order = 0)]
+    #[sk(prefix = "SKU", order = 0)]
     #[nk]
     pub sku: u32,
 
-    #[nk(name = "PK", prefix = "ACC")]
+    #[pk(prefix = "ACC")]
     pub account_id: u32,
 }
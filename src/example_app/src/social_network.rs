@@ -1,6 +1,21 @@
+use chrono::NaiveDate;
 use entity_macros::Dynodmize;
+use serde::Serialize;
 
-#[derive(Dynodmize)]
+#[derive(Dynodmize, Serialize)]
+#[dynodmize(table = "social")]
+#[pk(name = "pk", value_suffix = "follower")]
+#[sk(name = "sk")]
+#[gsi(name = "InvertedIndex", pk = "sk", sk = "pk")]
+pub struct UserFollower {
+    #[pk(prefix = "u")]
+    pub(crate) user_id: u32,
+    #[sk(prefix = "u")]
+    pub(crate) follower_id: u32,
+}
+
+#[derive(Dynodmize, Serialize)]
+#[dynodmize(table = "social")]
 #[pk(name = "pk")]
 #[sk(name = "sk", value = "count")]
 pub struct UserCount {
@@ -13,3 +28,37 @@ pub struct UserCount {
     #[nk]
     pub(crate) posts: usize,
 }
+
+#[derive(Debug, Dynodmize, Serialize)]
+#[pk(name = "pk")]
+#[sk(name = "sk", value = "profile")]
+#[gsi(name = "VerifiedIndex", pk = "gsi1pk", sk = "gsi1sk")]
+pub struct UserProfile {
+    #[pk(prefix = "u")]
+    #[gsi(name = "VerifiedIndex", key = "sk", prefix = "u")]
+    pub(crate) user_id: u32,
+    /// Only verified users are in `VerifiedIndex`
+    #[gsi(name = "VerifiedIndex", key = "pk", prefix = "verified", format = "%Y-%m-%d")]
+    pub(crate) verified_on: Option<NaiveDate>,
+}
+
+/// Everything stored in a user's partition
+#[derive(Debug, Dynodmize)]
+#[pk(name = "pk")]
+pub enum UserPartition {
+    #[sk(name = "sk", value = "profile")]
+    Profile {
+        #[pk(prefix = "u")]
+        user_id: u32,
+        display_name: String,
+    },
+    #[sk(name = "sk")]
+    #[dynodmize(tag = "post")]
+    Post {
+        #[pk(prefix = "u")]
+        user_id: u32,
+        #[sk(prefix = "p", pad = 10)]
+        post_id: u32,
+        title: String,
+    },
+}